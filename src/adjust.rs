@@ -0,0 +1,111 @@
+use eframe::egui;
+use image::DynamicImage;
+
+/// Display-time tonal adjustments. The defaults leave the image unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adjustments {
+    /// Stops, -5 to 5
+    pub exposure: f32,
+    /// -1 to 1, around middle gray
+    pub contrast: f32,
+    pub gamma: f32,
+    /// 0 is grayscale, 1 unchanged
+    pub saturation: f32,
+    /// Warm (positive) or cool (negative), -1 to 1
+    pub temperature: f32,
+    /// Magenta (positive) or green (negative), -1 to 1
+    pub tint: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            temperature: 0.0,
+            tint: 0.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Per-channel lookup of everything but saturation, which mixes channels.
+    fn tone_curves(&self) -> [[f32; 256]; 3] {
+        let balance = [
+            1.0 + 0.3 * self.temperature,
+            1.0 - 0.3 * self.tint,
+            1.0 - 0.3 * self.temperature,
+        ];
+        let gain = 2f32.powf(self.exposure);
+        let inverse_gamma = 1.0 / self.gamma.max(0.01);
+
+        balance.map(|balance| {
+            let mut curve = [0.0; 256];
+            for (value, out) in curve.iter_mut().enumerate() {
+                let x = value as f32 / 255.0 * balance * gain;
+                let x = ((x - 0.5) * (1.0 + self.contrast) + 0.5).clamp(0.0, 1.0);
+                *out = x.powf(inverse_gamma);
+            }
+            curve
+        })
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        if self.is_identity() {
+            return image.clone();
+        }
+        let curves = self.tone_curves();
+        let mut rgba = image.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            let rgb = [curves[0][r as usize], curves[1][g as usize], curves[2][b as usize]];
+            let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            let [r, g, b] = rgb.map(|c| ((luma + (c - luma) * self.saturation).clamp(0.0, 1.0) * 255.0).round() as u8);
+            pixel.0 = [r, g, b, a];
+        }
+        DynamicImage::ImageRgba8(rgba)
+    }
+
+    /// Sliders for each adjustment; returns true if any changed.
+    pub fn sliders(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        egui::Grid::new("adjustment_sliders").num_columns(2).show(ui, |ui| {
+            let mut slider = |ui: &mut egui::Ui, label: &str, value: &mut f32, range| {
+                ui.label(label);
+                changed |= ui.add(egui::Slider::new(value, range)).changed();
+                ui.end_row();
+            };
+            slider(ui, "Exposure", &mut self.exposure, -5.0..=5.0);
+            slider(ui, "Contrast", &mut self.contrast, -1.0..=1.0);
+            slider(ui, "Gamma", &mut self.gamma, 0.2..=5.0);
+            slider(ui, "Saturation", &mut self.saturation, 0.0..=2.0);
+            slider(ui, "Temperature", &mut self.temperature, -1.0..=1.0);
+            slider(ui, "Tint", &mut self.tint, -1.0..=1.0);
+        });
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjustments() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([64, 128, 32, 77])));
+        assert_eq!(Adjustments::default().apply(&image).to_rgba8().get_pixel(0, 0).0, [64, 128, 32, 77]);
+
+        let brighter = Adjustments { exposure: 1.0, ..Default::default() };
+        assert_eq!(brighter.apply(&image).to_rgba8().get_pixel(0, 0).0, [128, 255, 64, 77]);
+
+        let gray = Adjustments { saturation: 0.0, ..Default::default() };
+        let [r, g, b, _] = gray.apply(&image).to_rgba8().get_pixel(0, 0).0;
+        assert!(r == g && g == b);
+    }
+}
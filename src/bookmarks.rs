@@ -0,0 +1,237 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// What to do when the target directory already has a file with that name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    /// Pick a free name: `photo_1.jpg`, `photo_2.jpg`, ...
+    #[default]
+    Rename,
+    Skip,
+    Overwrite,
+}
+
+impl std::str::FromStr for Collision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(Collision::Rename),
+            "skip" => Ok(Collision::Skip),
+            "overwrite" => Ok(Collision::Overwrite),
+            _ => Err(format!("invalid collision mode '{}' (expected rename, skip or overwrite)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Copy,
+    Move,
+}
+
+struct Entry {
+    source: PathBuf,
+    dest: PathBuf,
+    /// Where an overwritten file was stashed, so undo can put it back
+    backup: Option<PathBuf>,
+}
+
+/// A finished copy or move that can be undone.
+pub struct Operation {
+    pub transfer: Transfer,
+    pub dir: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Operation {
+    /// Source paths that were actually transferred.
+    pub fn sources(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.iter().map(|e| &e.source)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Revert the operation, newest entry first.
+    pub fn undo(mut self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for entry in self.entries.iter_mut().rev() {
+            let result = match self.transfer {
+                Transfer::Copy => std::fs::remove_file(&entry.dest),
+                Transfer::Move => move_file(&entry.dest, &entry.source),
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", entry.dest.display(), e));
+                continue;
+            }
+            if let Some(backup) = entry.backup.take()
+                && let Err(e) = std::fs::rename(&backup, &entry.dest)
+            {
+                errors.push(format!("{}: {}", entry.dest.display(), e));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors.join("; ")) }
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        // Overwritten files are only kept around for as long as undo is possible
+        for backup in self.entries.iter().filter_map(|e| e.backup.as_ref()) {
+            let _ = std::fs::remove_file(backup);
+        }
+    }
+}
+
+/// `~/foo` → `$HOME/foo`.
+pub fn expand_home(path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~")
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        return PathBuf::from(home).join(rest);
+    }
+    path.to_path_buf()
+}
+
+/// Copy or move `paths` into `dir`. Returns what was done along with a
+/// message for each file that failed or was skipped.
+pub fn transfer(paths: &[PathBuf], dir: &Path, transfer: Transfer, collision: Collision) -> (Operation, Vec<String>) {
+    let mut operation = Operation {
+        transfer,
+        dir: dir.to_path_buf(),
+        entries: Vec::new(),
+    };
+    let mut errors = Vec::new();
+
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        errors.push(format!("{}: {}", dir.display(), e));
+        return (operation, errors);
+    }
+
+    for source in paths {
+        let Some(name) = source.file_name() else { continue };
+        let mut dest = dir.join(name);
+        let mut backup = None;
+
+        if dest.exists() {
+            if same_file(source, &dest) {
+                errors.push(format!("{}: already in {}", source.display(), dir.display()));
+                continue;
+            }
+            match collision {
+                Collision::Skip => {
+                    errors.push(format!("{}: skipped, {} exists", source.display(), dest.display()));
+                    continue;
+                }
+                Collision::Rename => dest = free_name(&dest),
+                Collision::Overwrite => {
+                    let stash = backup_name(&dest);
+                    if let Err(e) = std::fs::rename(&dest, &stash) {
+                        errors.push(format!("{}: {}", dest.display(), e));
+                        continue;
+                    }
+                    backup = Some(stash);
+                }
+            }
+        }
+
+        let result = match transfer {
+            Transfer::Copy => std::fs::copy(source, &dest).map(|_| ()),
+            Transfer::Move => move_file(source, &dest),
+        };
+        match result {
+            Ok(()) => operation.entries.push(Entry {
+                source: source.clone(),
+                dest,
+                backup,
+            }),
+            Err(e) => {
+                if let Some(stash) = backup {
+                    let _ = std::fs::rename(&stash, &dest);
+                }
+                errors.push(format!("{}: {}", source.display(), e));
+            }
+        }
+    }
+
+    (operation, errors)
+}
+
+/// Rename, falling back to copy and delete across filesystems.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to)?;
+    std::fs::remove_file(from)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+fn backup_name(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    free_name(&path.with_file_name(format!(".{}.img-undo", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_with_rename_and_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("keep");
+        let source = dir.path().join("a.png");
+        std::fs::write(&source, b"new").unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("a.png"), b"old").unwrap();
+
+        let (op, errors) = transfer(std::slice::from_ref(&source), &target, Transfer::Move, Collision::Rename);
+        assert!(errors.is_empty());
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(target.join("a_1.png")).unwrap(), b"new");
+
+        op.undo().unwrap();
+        assert_eq!(std::fs::read(&source).unwrap(), b"new");
+        assert!(!target.join("a_1.png").exists());
+    }
+
+    #[test]
+    fn test_overwrite_is_undoable_and_skip_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("keep");
+        let source = dir.path().join("a.png");
+        std::fs::write(&source, b"new").unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("a.png"), b"old").unwrap();
+
+        let (op, errors) = transfer(std::slice::from_ref(&source), &target, Transfer::Copy, Collision::Skip);
+        assert!(op.is_empty());
+        assert_eq!(errors.len(), 1);
+
+        let (op, errors) = transfer(std::slice::from_ref(&source), &target, Transfer::Copy, Collision::Overwrite);
+        assert!(errors.is_empty());
+        assert_eq!(std::fs::read(target.join("a.png")).unwrap(), b"new");
+
+        op.undo().unwrap();
+        assert_eq!(std::fs::read(target.join("a.png")).unwrap(), b"old");
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 1);
+    }
+}
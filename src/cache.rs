@@ -0,0 +1,159 @@
+use eframe::egui;
+use image::{DynamicImage, GenericImageView};
+use lru::LruCache;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Memory budget of the viewer's cache, in bytes of decoded pixels.
+pub const DEFAULT_CACHE_BYTES: usize = 1 << 30;
+
+/// A decoded image, shrunk for display, with the view state that goes with it.
+#[derive(Clone)]
+pub struct CachedImage {
+    pub display_image: DynamicImage,
+    /// Texture along with the sampling it was created with
+    pub texture: Option<(egui::TextureHandle, egui::TextureOptions)>,
+    /// Clockwise, in degrees
+    pub rotation: u32,
+    /// Dimensions of the decoded file before resizing for display
+    pub original_size: (u32, u32),
+}
+
+impl CachedImage {
+    pub fn new(display_image: DynamicImage, original_size: (u32, u32)) -> Self {
+        Self {
+            display_image,
+            texture: None,
+            rotation: 0,
+            original_size,
+        }
+    }
+
+    /// Memory taken by the pixels of the display copy.
+    pub fn bytes(&self) -> usize {
+        self.display_image.as_bytes().len()
+    }
+
+    /// Size of the display copy once rotated.
+    pub fn rotated_size(&self) -> (u32, u32) {
+        let (w, h) = self.display_image.dimensions();
+        if self.rotation % 180 == 90 { (h, w) } else { (w, h) }
+    }
+}
+
+/// Decoded images by path. The least recently used ones are dropped once
+/// their pixels take more than the budget, but the newest entry is always
+/// kept, however large.
+pub struct ImageCache {
+    // Each entry with the bytes it was counted as when inserted
+    entries: LruCache<PathBuf, (CachedImage, usize)>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+/// The cache as shared between the UI and the decoding threads.
+pub type SharedCache = Arc<Mutex<ImageCache>>;
+
+impl ImageCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn shared(max_bytes: usize) -> SharedCache {
+        Arc::new(Mutex::new(Self::new(max_bytes)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Pixel memory currently held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.contains(path)
+    }
+
+    /// Look up `path`, marking it as recently used.
+    pub fn get(&mut self, path: &Path) -> Option<&CachedImage> {
+        self.entries.get(path).map(|(image, _)| image)
+    }
+
+    pub fn get_mut(&mut self, path: &Path) -> Option<&mut CachedImage> {
+        self.entries.get_mut(path).map(|(image, _)| image)
+    }
+
+    /// Look up `path` without changing the eviction order.
+    pub fn peek(&self, path: &Path) -> Option<&CachedImage> {
+        self.entries.peek(path).map(|(image, _)| image)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.iter().map(|(path, _)| path)
+    }
+
+    pub fn put(&mut self, path: PathBuf, image: CachedImage) {
+        let bytes = image.bytes();
+        self.bytes += bytes;
+        if let Some((_, (_, old))) = self.entries.push(path, (image, bytes)) {
+            self.bytes -= old;
+        }
+        while self.bytes > self.max_bytes && self.entries.len() > 1 {
+            if let Some((_, (_, old))) = self.entries.pop_lru() {
+                self.bytes -= old;
+            }
+        }
+    }
+
+    pub fn pop(&mut self, path: &Path) -> Option<CachedImage> {
+        let (image, bytes) = self.entries.pop(path)?;
+        self.bytes -= bytes;
+        Some(image)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_by_size() {
+        // 10x10 RGB is 300 bytes
+        let image = || CachedImage::new(DynamicImage::new_rgb8(10, 10), (10, 10));
+        let mut cache = ImageCache::new(700);
+        cache.put(PathBuf::from("a"), image());
+        cache.put(PathBuf::from("b"), image());
+        cache.get(Path::new("a"));
+        cache.put(PathBuf::from("c"), image());
+        assert!(!cache.contains(Path::new("b")));
+        assert_eq!((cache.len(), cache.bytes()), (2, 600));
+
+        cache.put(PathBuf::from("a"), image());
+        assert_eq!(cache.bytes(), 600);
+        assert!(cache.pop(Path::new("a")).is_some());
+        assert_eq!(cache.bytes(), 300);
+
+        // Too big for the budget on its own, but kept since it's the newest
+        cache.put(PathBuf::from("big"), CachedImage::new(DynamicImage::new_rgb8(20, 20), (20, 20)));
+        assert_eq!(cache.paths().collect::<Vec<_>>(), vec![&PathBuf::from("big")]);
+    }
+}
@@ -0,0 +1,397 @@
+use crate::bookmarks::Collision;
+use crate::export::{Format, PngCompression};
+use crate::montage::Layout;
+use crate::terminal::Protocol;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: img [OPTIONS] [PATH...]
+       img convert [OPTIONS] --to <FORMAT> --out <DIR> <PATH...>
+       img info [--json] <PATH...>
+       img montage [OPTIONS] --out <FILE> <PATH...>
+
+Paths may be image files or directories. Use `-` to read a path list from stdin.
+
+Options:
+  --files-from <FILE>  Read newline- or NUL-separated paths from FILE (`-` for stdin)
+  -o, --output-marked  Print marked paths to stdout when quitting with `q`
+  -0, --null           Paths on stdin and marked output are NUL-separated
+                       (stdin is otherwise detected like --files-from)
+  --key-handler <FILE> Script run by Ctrl+X <key> with the paths on stdin;
+                       paths it prints replace moved files (default:
+                       $XDG_CONFIG_HOME/img/exec/key-handler)
+  --bookmark <N=DIR>   Number key N (1-9) copies to DIR, Ctrl+N moves there
+  --on-collision <MODE>
+                       rename, skip or overwrite existing files in bookmarks
+  --tty                Draw in the terminal instead of a window (the default
+                       when neither DISPLAY nor WAYLAND_DISPLAY is set)
+  --tty-protocol <P>   kitty, iterm, sixel or blocks (default: detected)
+  --skip-broken        Step over images that fail to decode
+  --strict             Decode every image first; list the ones that fail and
+                       exit with status 1 if there are any (paths on stdin
+                       are all read before the window opens)
+  --no-restore         Start a directory afresh instead of where it was left
+                       (its state is still saved on exit)
+  -h, --help           Show this help";
+
+pub const CONVERT_USAGE: &str = "\
+Usage: img convert [OPTIONS] --to <FORMAT> --out <DIR> <PATH...>
+
+Convert images without opening a window. Directories are scanned for images.
+
+Options:
+  --to <FORMAT>        png, jpg or webp
+  --out <DIR>          Directory for the converted files, created if missing;
+                       subdirectories of scanned directories are kept
+  --max-size <PX>      Shrink images so neither side exceeds PX
+  --quality <1-100>    JPEG quality (default 90), or lossy WebP quality
+                       (WebP is lossless without it)
+  --compression <MODE> PNG compression: fast, default or best
+  --rotate <DEG>       Rotate clockwise by 90, 180 or 270 degrees
+  -j, --jobs <N>       Parallel decodes (default: number of CPUs)
+  -h, --help           Show this help";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    /// Files and directories given on the command line, in order
+    pub paths: Vec<PathBuf>,
+    /// Path list to read, `-` meaning stdin
+    pub files_from: Option<PathBuf>,
+    pub null_separated: bool,
+    pub output_marked: bool,
+    pub key_handler: Option<PathBuf>,
+    pub bookmarks: Vec<(u8, PathBuf)>,
+    pub collision: Option<Collision>,
+    pub tty: bool,
+    pub tty_protocol: Option<Protocol>,
+    pub skip_broken: bool,
+    pub strict: bool,
+    pub no_restore: bool,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-" => parsed.files_from = Some(PathBuf::from("-")),
+                "--files-from" => {
+                    let value = args.next().ok_or("--files-from requires a file")?;
+                    parsed.files_from = Some(PathBuf::from(value));
+                }
+                "--key-handler" => {
+                    let value = args.next().ok_or("--key-handler requires a file")?;
+                    parsed.key_handler = Some(PathBuf::from(value));
+                }
+                "--bookmark" => {
+                    let value = args.next().ok_or("--bookmark requires N=DIR")?;
+                    parsed.bookmarks.push(parse_bookmark(&value)?);
+                }
+                "--on-collision" => {
+                    let value = args.next().ok_or("--on-collision requires a mode")?;
+                    parsed.collision = Some(value.parse()?);
+                }
+                "--tty-protocol" => {
+                    let value = args.next().ok_or("--tty-protocol requires a protocol")?;
+                    parsed.tty_protocol = Some(value.parse()?);
+                }
+                "--tty" => parsed.tty = true,
+                "--skip-broken" => parsed.skip_broken = true,
+                "--strict" => parsed.strict = true,
+                "--no-restore" => parsed.no_restore = true,
+                "-0" | "--null" => parsed.null_separated = true,
+                "-o" | "--output-marked" => parsed.output_marked = true,
+                "-h" | "--help" => parsed.help = true,
+                "--" => parsed.paths.extend(args.by_ref().map(PathBuf::from)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => parsed.paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        if parsed.paths.is_empty() && parsed.files_from.is_none() {
+            parsed.paths.push(PathBuf::from("."));
+        }
+        Ok(parsed)
+    }
+
+    pub fn reads_stdin(&self) -> bool {
+        self.files_from.as_deref() == Some(std::path::Path::new("-"))
+    }
+}
+
+pub const INFO_USAGE: &str = "\
+Usage: img info [--json] <PATH...>
+
+Print format, dimensions, color type, frame count, file size and EXIF data.
+Exits with status 1 if any file can't be decoded.
+
+Options:
+  --json      Print a JSON array instead of a table
+  -h, --help  Show this help";
+
+#[derive(Debug, PartialEq)]
+pub struct InfoArgs {
+    pub paths: Vec<PathBuf>,
+    pub json: bool,
+}
+
+impl InfoArgs {
+    /// Parse the arguments after `info`. `Ok(None)` means help was asked for.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut parsed = InfoArgs { paths: Vec::new(), json: false };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => parsed.json = true,
+                "-h" | "--help" => return Ok(None),
+                "--" => parsed.paths.extend(args.by_ref().map(PathBuf::from)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => parsed.paths.push(PathBuf::from(arg)),
+            }
+        }
+        if parsed.paths.is_empty() {
+            return Err("no input files".to_string());
+        }
+        Ok(Some(parsed))
+    }
+}
+
+pub const MONTAGE_USAGE: &str = "\
+Usage: img montage [OPTIONS] --out <FILE> <PATH...>
+
+Lay out thumbnails in a grid on one or more contact sheets.
+
+Options:
+  --out <FILE>         Sheet to write (.png, .jpg or .webp); when the images
+                       need more than one sheet, they're numbered FILE-1,
+                       FILE-2, ...
+  --cols <N>           Thumbnails per row (default 6)
+  --rows <N>           Rows per sheet (default 8)
+  --tile <PX>          Thumbnail size (default 256)
+  --spacing <PX>       Gap between thumbnails (default 8)
+  --background <COLOR> Sheet color as #rrggbb (default #202020)
+  --labels             Caption each thumbnail with its file name
+  -h, --help           Show this help";
+
+#[derive(Debug, PartialEq)]
+pub struct MontageArgs {
+    pub inputs: Vec<PathBuf>,
+    pub out: PathBuf,
+    pub layout: Layout,
+}
+
+impl MontageArgs {
+    /// Parse the arguments after `montage`. `Ok(None)` means help was asked for.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut inputs = Vec::new();
+        let mut out = None;
+        let mut layout = Layout {
+            labels: false,
+            ..Layout::default()
+        };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} requires a value", name));
+            let mut number = |name: &str| -> Result<u32, String> {
+                let text = value(name)?;
+                text.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid {} '{}'", name, text))
+            };
+            match arg.as_str() {
+                "--out" => out = Some(PathBuf::from(value("--out")?)),
+                "--cols" => layout.cols = number("--cols")?,
+                "--rows" => layout.rows = number("--rows")?,
+                "--tile" => layout.tile = number("--tile")?,
+                "--spacing" => layout.spacing = value("--spacing")?.parse().map_err(|_| "invalid --spacing")?,
+                "--background" => layout.background = value("--background")?.parse()?,
+                "--labels" => layout.labels = true,
+                "-h" | "--help" => return Ok(None),
+                "--" => inputs.extend(args.by_ref().map(PathBuf::from)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => inputs.push(PathBuf::from(arg)),
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err("no input files".to_string());
+        }
+        let out = out.ok_or("--out is required")?;
+        Ok(Some(MontageArgs { inputs, out, layout }))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConvertArgs {
+    pub inputs: Vec<PathBuf>,
+    pub to: Format,
+    pub out: PathBuf,
+    pub max_size: Option<u32>,
+    /// JPEG quality, or lossy WebP quality; ignored for PNG
+    pub quality: Option<u8>,
+    pub compression: PngCompression,
+    pub rotate: u32,
+    pub jobs: Option<usize>,
+}
+
+impl ConvertArgs {
+    /// Parse the arguments after `convert`. `Ok(None)` means help was asked for.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut inputs = Vec::new();
+        let (mut to, mut out, mut max_size, mut jobs) = (None, None, None, None);
+        let (mut quality, mut compression, mut rotate) = (None, PngCompression::Default, 0);
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} requires a value", name));
+            match arg.as_str() {
+                "--to" => {
+                    let name = value("--to")?;
+                    to = Some(Format::from_extension(&name).ok_or_else(|| format!("unsupported format '{}'", name))?);
+                }
+                "--out" => out = Some(PathBuf::from(value("--out")?)),
+                "--max-size" => {
+                    let size = value("--max-size")?;
+                    max_size = Some(size.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid size '{}'", size))?);
+                }
+                "--quality" => {
+                    let q = value("--quality")?;
+                    quality = Some(q.parse().ok().filter(|q| (1..=100).contains(q)).ok_or_else(|| format!("invalid quality '{}'", q))?);
+                }
+                "--compression" => compression = value("--compression")?.parse()?,
+                "--rotate" => {
+                    let degrees = value("--rotate")?;
+                    rotate = match degrees.as_str() {
+                        "0" | "90" | "180" | "270" => degrees.parse().unwrap(),
+                        _ => return Err(format!("invalid rotation '{}' (90, 180 or 270)", degrees)),
+                    };
+                }
+                "-j" | "--jobs" => {
+                    let n = value("--jobs")?;
+                    jobs = Some(n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid job count '{}'", n))?);
+                }
+                "-h" | "--help" => return Ok(None),
+                "--" => inputs.extend(args.by_ref().map(PathBuf::from)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => inputs.push(PathBuf::from(arg)),
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err("no input files".to_string());
+        }
+        Ok(Some(ConvertArgs {
+            inputs,
+            to: to.ok_or("--to is required")?,
+            out: out.ok_or("--out is required")?,
+            max_size,
+            quality,
+            compression,
+            rotate,
+            jobs,
+        }))
+    }
+}
+
+fn parse_bookmark(value: &str) -> Result<(u8, PathBuf), String> {
+    let (slot, dir) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid bookmark '{}' (expected N=DIR)", value))?;
+    match slot.parse::<u8>() {
+        Ok(slot @ 1..=9) if !dir.is_empty() => Ok((slot, PathBuf::from(dir))),
+        _ => Err(format!("invalid bookmark '{}' (expected N=DIR with N in 1-9)", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_defaults_to_current_dir() {
+        assert_eq!(parse(&[]).unwrap().paths, vec![PathBuf::from(".")]);
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let args = parse(&["--bookmark", "1=~/keep", "--bookmark", "2=/tmp/reject", "--on-collision", "skip"]).unwrap();
+        assert_eq!(args.bookmarks, vec![(1, PathBuf::from("~/keep")), (2, PathBuf::from("/tmp/reject"))]);
+        assert_eq!(args.collision, Some(Collision::Skip));
+        assert!(parse(&["--on-collision", "merge"]).is_err());
+        assert!(parse(&["--bookmark", "0=/tmp"]).is_err());
+    }
+
+    #[test]
+    fn test_path_list_sources() {
+        let args = parse(&["-", "-0"]).unwrap();
+        assert!(args.reads_stdin());
+        assert!(args.null_separated);
+        assert!(args.paths.is_empty());
+
+        let args = parse(&["a.png", "--files-from", "list.txt", "dir"]).unwrap();
+        assert_eq!(args.files_from, Some(PathBuf::from("list.txt")));
+        assert_eq!(args.paths, vec![PathBuf::from("a.png"), PathBuf::from("dir")]);
+
+        assert!(parse(&["--files-from"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_tty() {
+        let args = parse(&["--tty", "--tty-protocol", "sixel"]).unwrap();
+        assert!(args.tty);
+        assert_eq!(args.tty_protocol, Some(Protocol::Sixel));
+        assert!(parse(&["--tty-protocol", "braille"]).is_err());
+    }
+
+    #[test]
+    fn test_broken_file_flags() {
+        let args = parse(&["--strict", "--skip-broken", "shots"]).unwrap();
+        assert!(args.strict && args.skip_broken);
+        assert_eq!(args.paths, vec![PathBuf::from("shots")]);
+    }
+
+    #[test]
+    fn test_no_restore() {
+        assert!(!parse(&[]).unwrap().no_restore);
+        assert!(parse(&["--no-restore", "shots"]).unwrap().no_restore);
+    }
+
+    #[test]
+    fn test_montage_args() {
+        let parse = |args: &[&str]| MontageArgs::parse(args.iter().map(|s| s.to_string()));
+        let args = parse(&["shots", "--cols", "4", "--tile", "128", "--labels", "--out", "sheet.png"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.inputs, vec![PathBuf::from("shots")]);
+        assert_eq!((args.layout.cols, args.layout.tile, args.layout.labels), (4, 128, true));
+        assert!(parse(&["shots", "--out", "s.png", "--cols", "0"]).is_err());
+        assert!(parse(&["shots", "--out", "s.png", "--background", "grey"]).is_err());
+    }
+
+    #[test]
+    fn test_convert_args() {
+        let parse = |args: &[&str]| ConvertArgs::parse(args.iter().map(|s| s.to_string()));
+        let args = parse(&["a.png", "--to", "webp", "--max-size", "1920", "--quality", "85", "--out", "dir/", "photos"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.inputs, vec![PathBuf::from("a.png"), PathBuf::from("photos")]);
+        assert_eq!(args.to, Format::WebP);
+        assert_eq!(args.max_size, Some(1920));
+        assert_eq!(args.quality, Some(85));
+
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert!(parse(&["a.png", "--to", "tga", "--out", "x"]).is_err());
+        assert!(parse(&["a.png", "--to", "jpg", "--out", "x", "--quality", "0"]).is_err());
+        assert_eq!(parse(&["a.png", "--to", "jpg", "--out", "x"]).unwrap().unwrap().quality, None);
+        assert_eq!(parse(&["a.png", "--to", "jpg", "--out", "x", "--quality", "85"]).unwrap().unwrap().quality, Some(85));
+        assert_eq!(parse(&["a.png", "--to", "png", "--out", "x", "--quality", "85"]).unwrap().unwrap().quality, Some(85));
+        assert!(parse(&["a.png", "--to", "jpg"]).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use eframe::egui;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Images are compared at no more than this size so metrics stay quick.
+const METRIC_SIZE: u32 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Peak signal-to-noise ratio in dB, infinite for identical images
+    pub psnr: f64,
+    /// Mean structural similarity over 8x8 windows, 1.0 for identical images
+    pub ssim: f64,
+}
+
+fn luma(img: &DynamicImage, w: u32, h: u32) -> Vec<f64> {
+    let img = if img.dimensions() == (w, h) {
+        img.to_luma8()
+    } else {
+        img.resize_exact(w, h, image::imageops::FilterType::Triangle).to_luma8()
+    };
+    img.pixels().map(|p| p.0[0] as f64).collect()
+}
+
+/// Size both images are compared at: A's size, scaled down to `METRIC_SIZE`.
+fn metric_dimensions(a: &DynamicImage) -> (u32, u32) {
+    let (w, h) = a.dimensions();
+    let scale = (METRIC_SIZE as f64 / w.max(h) as f64).min(1.0);
+    (((w as f64 * scale) as u32).max(1), ((h as f64 * scale) as u32).max(1))
+}
+
+pub fn metrics(a: &DynamicImage, b: &DynamicImage) -> Metrics {
+    let (w, h) = metric_dimensions(a);
+    let (la, lb) = (luma(a, w, h), luma(b, w, h));
+
+    let mse = la.iter().zip(&lb).map(|(x, y)| (x - y).powi(2)).sum::<f64>() / la.len() as f64;
+    let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0f64.powi(2) / mse).log10() };
+
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (w, h) = (w as usize, h as usize);
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in (0..h.saturating_sub(7)).step_by(4) {
+        for x0 in (0..w.saturating_sub(7)).step_by(4) {
+            let pixels = (y0..y0 + 8).flat_map(|y| (x0..x0 + 8).map(move |x| y * w + x));
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for i in pixels {
+                let (x, y) = (la[i], lb[i]);
+                sa += x;
+                sb += y;
+                saa += x * x;
+                sbb += y * y;
+                sab += x * y;
+            }
+            let n = 64.0;
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb, cov) = (saa / n - ma * ma, sbb / n - mb * mb, sab / n - ma * mb);
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2)) / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+    let ssim = if windows == 0 { 1.0 } else { total / windows as f64 };
+
+    Metrics { psnr, ssim }
+}
+
+/// Per-pixel absolute difference, black for identical through red and
+/// yellow to white for the largest differences.
+pub fn difference_heatmap(a: &DynamicImage, b: &DynamicImage) -> RgbaImage {
+    let (w, h) = metric_dimensions(a);
+    let a = a.resize_exact(w, h, image::imageops::FilterType::Triangle).to_rgb8();
+    let b = b.resize_exact(w, h, image::imageops::FilterType::Triangle).to_rgb8();
+
+    RgbaImage::from_fn(w, h, |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y).0, b.get_pixel(x, y).0);
+        let diff = (0..3).map(|c| pa[c].abs_diff(pb[c]) as f32).fold(0.0, f32::max) / 255.0;
+        // Boost small differences so they stay visible
+        let t = diff.sqrt() * 3.0;
+        let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+        image::Rgba([channel(t), channel(t - 1.0), channel(t - 2.0), 255])
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareView {
+    SideBySide,
+    Split,
+    Flicker,
+    Difference,
+}
+
+impl CompareView {
+    fn next(self) -> Self {
+        match self {
+            CompareView::SideBySide => CompareView::Split,
+            CompareView::Split => CompareView::Flicker,
+            CompareView::Flicker => CompareView::Difference,
+            CompareView::Difference => CompareView::SideBySide,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            CompareView::SideBySide => "side by side",
+            CompareView::Split => "split",
+            CompareView::Flicker => "flicker",
+            CompareView::Difference => "difference",
+        }
+    }
+}
+
+struct Analysis {
+    b: (PathBuf, u32),
+    metrics: Metrics,
+    heatmap: egui::ColorImage,
+}
+
+/// Compare mode: image A is pinned, B follows normal navigation. Zoom and
+/// pan are shared by both sides.
+pub struct Compare {
+    a_path: PathBuf,
+    a_image: DynamicImage,
+    a_texture: egui::TextureHandle,
+    // Path and rotation of the B currently shown
+    b: Option<(PathBuf, u32)>,
+    b_texture: Option<egui::TextureHandle>,
+    view: CompareView,
+    // Position of the split line, 0..1 across the image
+    split: f32,
+    show_b: bool,
+    zoom: f32,
+    pan: egui::Vec2,
+    metrics: Option<Metrics>,
+    heatmap: Option<egui::TextureHandle>,
+    pending: Option<Receiver<Analysis>>,
+}
+
+impl Compare {
+    /// Pin `image` (already rotated for display) as image A.
+    pub fn new(path: PathBuf, image: DynamicImage, ctx: &egui::Context) -> Self {
+        let a_texture = ctx.load_texture("compare_a", to_color_image(&image), Default::default());
+        Self {
+            a_path: path,
+            a_image: image,
+            a_texture,
+            b: None,
+            b_texture: None,
+            view: CompareView::SideBySide,
+            split: 0.5,
+            show_b: true,
+            zoom: 1.0,
+            pan: egui::Vec2::ZERO,
+            metrics: None,
+            heatmap: None,
+            pending: None,
+        }
+    }
+
+    pub fn cycle_view(&mut self) {
+        self.view = self.view.next();
+    }
+
+    pub fn flicker(&mut self) {
+        self.view = CompareView::Flicker;
+        self.show_b = !self.show_b;
+    }
+
+    pub fn reset_view(&mut self) {
+        self.zoom = 1.0;
+        self.pan = egui::Vec2::ZERO;
+    }
+
+    /// Switch image B, starting the metrics and heatmap in the background.
+    fn set_b(&mut self, path: &Path, image: &DynamicImage, rotation: u32, ctx: &egui::Context) {
+        let key = (path.to_path_buf(), rotation);
+        if self.b.as_ref() == Some(&key) {
+            return;
+        }
+        let image = crate::rotate_image(image, rotation);
+        self.b = Some(key.clone());
+        self.b_texture = Some(ctx.load_texture("compare_b", to_color_image(&image), Default::default()));
+        self.metrics = None;
+        self.heatmap = None;
+
+        let (tx, rx) = mpsc::channel();
+        let (a, ctx) = (self.a_image.clone(), ctx.clone());
+        std::thread::spawn(move || {
+            let metrics = metrics(&a, &image);
+            let heatmap = difference_heatmap(&a, &image);
+            let size = [heatmap.width() as usize, heatmap.height() as usize];
+            let heatmap = egui::ColorImage::from_rgba_unmultiplied(size, &heatmap);
+            let _ = tx.send(Analysis { b: key, metrics, heatmap });
+            ctx.request_repaint();
+        });
+        self.pending = Some(rx);
+    }
+
+    fn poll_analysis(&mut self, ctx: &egui::Context) {
+        let Some(analysis) = self.pending.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return;
+        };
+        self.pending = None;
+        // Ignore results for a B that has since been replaced
+        if self.b.as_ref() == Some(&analysis.b) {
+            self.heatmap = Some(ctx.load_texture("compare_diff", analysis.heatmap, Default::default()));
+            self.metrics = Some(analysis.metrics);
+        }
+    }
+
+    /// Where an image of `size` goes inside `area`, after fit, zoom and pan.
+    fn image_rect(&self, area: egui::Rect, size: egui::Vec2) -> egui::Rect {
+        let fit = (area.width() / size.x).min(area.height() / size.y);
+        egui::Rect::from_center_size(area.center() + self.pan, size * fit * self.zoom)
+    }
+
+    /// Draw the comparison. `b` is the viewer's current image and its
+    /// rotation, if loaded.
+    pub fn show(&mut self, ui: &mut egui::Ui, b: Option<(&PathBuf, &DynamicImage, u32)>) {
+        let ctx = ui.ctx().clone();
+        if let Some((path, image, rotation)) = b {
+            self.set_b(path, image, rotation, &ctx);
+        }
+        self.poll_analysis(&ctx);
+
+        let area = ui.available_rect_before_wrap();
+        let response = ui.allocate_rect(area, egui::Sense::drag());
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+        let a_size = self.a_texture.size_vec2();
+        let painter = ui.painter_at(area);
+
+        // Split handle first so dragging it doesn't also pan
+        let full = self.image_rect(area, a_size);
+        let split_x = full.left() + full.width() * self.split;
+        let handle = egui::Rect::from_x_y_ranges(split_x - 6.0..=split_x + 6.0, area.y_range());
+        let handle_response = ui.interact(handle, ui.id().with("compare_split"), egui::Sense::drag());
+
+        if self.view == CompareView::Split && handle_response.dragged() {
+            self.split = (self.split + handle_response.drag_delta().x / full.width()).clamp(0.0, 1.0);
+        } else if response.dragged() {
+            self.pan += response.drag_delta();
+        }
+        if response.hovered() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            if scroll != 0.0 {
+                let factor = (scroll / 200.0).exp();
+                // Zoom around the pointer
+                if let Some(pointer) = response.hover_pos() {
+                    let center = area.center() + self.pan;
+                    self.pan += (center - pointer) * (factor - 1.0);
+                }
+                self.zoom = (self.zoom * factor).clamp(0.05, 64.0);
+            }
+        }
+
+        let b_texture = self.b_texture.as_ref();
+        match self.view {
+            CompareView::SideBySide => {
+                let (left, right) = area.split_left_right_at_fraction(0.5);
+                painter.with_clip_rect(left).image(self.a_texture.id(), self.image_rect(left, a_size), uv, egui::Color32::WHITE);
+                if let Some(b) = b_texture {
+                    painter.with_clip_rect(right).image(b.id(), self.image_rect(right, b.size_vec2()), uv, egui::Color32::WHITE);
+                }
+                painter.vline(area.center().x, area.y_range(), ui.visuals().window_stroke());
+            }
+            CompareView::Split => {
+                // B is stretched over A's rect so the pixels line up
+                let (left, right) = (
+                    egui::Rect::from_x_y_ranges(area.left()..=split_x, area.y_range()),
+                    egui::Rect::from_x_y_ranges(split_x..=area.right(), area.y_range()),
+                );
+                painter.with_clip_rect(left).image(self.a_texture.id(), full, uv, egui::Color32::WHITE);
+                if let Some(b) = b_texture {
+                    painter.with_clip_rect(right).image(b.id(), full, uv, egui::Color32::WHITE);
+                }
+                painter.vline(split_x, area.y_range(), egui::Stroke::new(2.0, egui::Color32::WHITE));
+            }
+            CompareView::Flicker => match b_texture {
+                Some(b) if self.show_b => painter.image(b.id(), self.image_rect(area, b.size_vec2()), uv, egui::Color32::WHITE),
+                _ => painter.image(self.a_texture.id(), full, uv, egui::Color32::WHITE),
+            },
+            CompareView::Difference => match &self.heatmap {
+                Some(heatmap) => painter.image(heatmap.id(), full, uv, egui::Color32::WHITE),
+                None => {
+                    let font = egui::FontId::proportional(16.0);
+                    let color = ui.visuals().text_color();
+                    painter.text(area.center(), egui::Align2::CENTER_CENTER, "Computing difference...", font, color);
+                }
+            },
+        }
+
+        let name = |path: &PathBuf| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let mut caption = format!("A: {}", name(&self.a_path));
+        if let Some((b_path, _)) = &self.b {
+            caption.push_str(&format!("   B: {}", name(b_path)));
+        }
+        if self.view == CompareView::Flicker {
+            caption.push_str(if self.show_b { "   [showing B]" } else { "   [showing A]" });
+        }
+        match &self.metrics {
+            Some(metrics) => {
+                let psnr = if metrics.psnr.is_infinite() {
+                    "∞".to_string()
+                } else {
+                    format!("{:.2} dB", metrics.psnr)
+                };
+                caption.push_str(&format!("   PSNR {}   SSIM {:.4}", psnr, metrics.ssim));
+            }
+            None if self.b.is_some() => caption.push_str("   measuring..."),
+            None => {}
+        }
+        caption.push_str(&format!("   ({}, V: view, X: flicker, C: exit)", self.view.label()));
+        painter.text(
+            area.left_top() + egui::vec2(8.0, 8.0),
+            egui::Align2::LEFT_TOP,
+            caption,
+            egui::FontId::proportional(14.0),
+            egui::Color32::from_rgb(255, 200, 0),
+        );
+    }
+}
+
+fn to_color_image(image: &DynamicImage) -> egui::ColorImage {
+    egui::ColorImage::from_rgba_unmultiplied([image.width() as usize, image.height() as usize], &image.to_rgba8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let a = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 0])));
+        let same = metrics(&a, &a);
+        assert!(same.psnr.is_infinite());
+        assert!((same.ssim - 1.0).abs() < 1e-9);
+
+        let mut b = a.to_rgb8();
+        for p in b.pixels_mut().step_by(7) {
+            p.0[0] = p.0[0].wrapping_add(40);
+        }
+        let different = metrics(&a, &DynamicImage::ImageRgb8(b));
+        assert!(different.psnr.is_finite() && different.psnr > 10.0);
+        assert!(different.ssim < 1.0);
+
+        let heatmap = difference_heatmap(&a, &a);
+        assert!(heatmap.pixels().all(|p| p.0 == [0, 0, 0, 255]));
+    }
+}
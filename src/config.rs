@@ -0,0 +1,97 @@
+use crate::bookmarks::Collision;
+use crate::transparency::BackgroundConfig;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Settings from `$XDG_CONFIG_HOME/img/config.toml`, e.g.
+///
+/// ```toml
+/// collision = "rename"
+/// skip_broken = true
+///
+/// [bookmarks]
+/// 1 = "~/keep"
+/// 2 = "~/reject"
+///
+/// [background]
+/// checker_size = 12
+/// checker_colors = ["#999999", "#666666"]
+/// custom = "#ff00ff"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Bookmark number (1-9) to target directory
+    #[serde(deserialize_with = "deserialize_bookmarks")]
+    pub bookmarks: BTreeMap<u8, PathBuf>,
+    pub collision: Collision,
+    pub background: BackgroundConfig,
+    /// Step over images that fail to decode
+    pub skip_broken: bool,
+}
+
+// TOML keys are always strings
+fn deserialize_bookmarks<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<u8, PathBuf>, D::Error> {
+    let raw = BTreeMap::<String, PathBuf>::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|(slot, dir)| match slot.parse::<u8>() {
+            Ok(n @ 1..=9) => Ok((n, dir)),
+            _ => Err(serde::de::Error::custom(format!("bookmark '{}' must be a number from 1 to 9", slot))),
+        })
+        .collect()
+}
+
+/// `$XDG_CONFIG_HOME/img`, falling back to `~/.config/img`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("img"))
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    /// Load the config file. A missing file gives the defaults; a broken one
+    /// is reported and ignored.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).unwrap_or_else(|e| {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("collision = \"skip\"\n[bookmarks]\n1 = \"~/keep\"\n2 = \"/tmp/reject\"\n").unwrap();
+        assert_eq!(config.collision, Collision::Skip);
+        assert_eq!(config.bookmarks.get(&1), Some(&PathBuf::from("~/keep")));
+        assert_eq!(config.bookmarks.len(), 2);
+
+        assert!(Config::parse("[bookmarks]\n0 = \"/tmp\"\n").is_err());
+        assert_eq!(Config::parse("").unwrap().collision, Collision::Rename);
+        assert!(Config::parse("skip_broken = true").unwrap().skip_broken);
+
+        let config = Config::parse("[background]\ncustom = \"#102030\"\n").unwrap();
+        assert_eq!(config.background.custom, Some(crate::transparency::Rgb([0x10, 0x20, 0x30])));
+        assert!(Config::parse("[background]\ncustom = \"red\"\n").is_err());
+    }
+}
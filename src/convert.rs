@@ -0,0 +1,197 @@
+use crate::cli::ConvertArgs;
+use crate::export::{self, EncodeOptions, Format};
+use crate::{loader, rotate_image, scanner};
+use image::imageops::FilterType;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+/// The images to convert, each with the path its output mirrors under
+/// `--out`: relative to the directory it was found in, or just the file
+/// name for files given directly.
+fn collect_inputs(roots: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    let scanner = scanner::Scanner::default();
+    let mut inputs = Vec::new();
+    for root in roots {
+        if root.is_dir() {
+            for path in scanner.scan(root) {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                inputs.push((path, relative));
+            }
+        } else {
+            let name = PathBuf::from(root.file_name().unwrap_or_default());
+            inputs.push((root.clone(), name));
+        }
+    }
+    inputs
+}
+
+/// Output path for each input, or why it can't be converted. Inputs that
+/// would land on the same output name, or on themselves, are refused.
+fn plan_outputs(inputs: &[(PathBuf, PathBuf)], args: &ConvertArgs) -> Vec<Result<PathBuf, String>> {
+    let mut claimed: HashMap<PathBuf, &Path> = HashMap::new();
+    inputs
+        .iter()
+        .map(|(input, relative)| {
+            if relative.file_stem().is_none() {
+                return Err("not a file name".to_string());
+            }
+            let output = args.out.join(relative).with_extension(args.to.extension());
+
+            if let (Ok(a), Ok(b)) = (output.canonicalize(), input.canonicalize())
+                && a == b
+            {
+                return Err("output would overwrite the input".to_string());
+            }
+            if let Some(other) = claimed.insert(output.clone(), input) {
+                return Err(format!("same output name as {}", other.display()));
+            }
+            Ok(output)
+        })
+        .collect()
+}
+
+fn convert_one(input: &Path, output: &Path, args: &ConvertArgs) -> Result<(), String> {
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    }
+    let mut image = image::open(input).map_err(|e| e.to_string())?;
+    if args.rotate != 0 {
+        image = rotate_image(&image, args.rotate);
+    }
+    if let Some(max_size) = args.max_size {
+        let (w, h) = (image.width(), image.height());
+        if w.max(h) > max_size {
+            image = loader::resize_for_display(&image, max_size, FilterType::Lanczos3);
+        }
+    }
+    let options = EncodeOptions {
+        format: args.to,
+        jpeg_quality: args.quality.unwrap_or(90),
+        png_compression: args.compression,
+        webp_quality: args.quality,
+    };
+    export::save(&image, output, &options)
+}
+
+fn draw_progress(done: usize, total: usize, failed: usize) {
+    const WIDTH: usize = 30;
+    let filled = done * WIDTH / total.max(1);
+    let failed = if failed > 0 { format!(", {} failed", failed) } else { String::new() };
+    eprint!("\r[{}{}] {}/{}{}", "#".repeat(filled), " ".repeat(WIDTH - filled), done, total, failed);
+    let _ = std::io::stderr().flush();
+}
+
+/// Convert all inputs, decoding on one thread per core. Returns the exit
+/// code: 0 if every file converted, 1 otherwise.
+pub fn run(args: &ConvertArgs) -> i32 {
+    let inputs = collect_inputs(&args.inputs);
+    if inputs.is_empty() {
+        eprintln!("img convert: no images found");
+        return 1;
+    }
+    if let Err(e) = std::fs::create_dir_all(&args.out) {
+        eprintln!("img convert: cannot create {}: {}", args.out.display(), e);
+        return 1;
+    }
+    if args.quality.is_some() && args.to == Format::Png {
+        eprintln!("img convert: --quality is ignored for png, which is lossless");
+    }
+
+    let outputs = plan_outputs(&inputs, args);
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(inputs.len());
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    let progress = std::io::stderr().is_terminal();
+
+    let mut failed = 0;
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (inputs, outputs, next) = (&inputs, &outputs, &next);
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((input, _)) = inputs.get(i) else { break };
+                    let result = match &outputs[i] {
+                        Ok(output) => convert_one(input, output, args),
+                        Err(e) => Err(e.clone()),
+                    };
+                    let _ = tx.send((i, result));
+                }
+            });
+        }
+        drop(tx);
+
+        for (done, (i, result)) in rx.iter().enumerate() {
+            if let Err(e) = result {
+                if progress {
+                    // Keep the message above the progress bar
+                    eprint!("\r\x1b[K");
+                }
+                eprintln!("img convert: {}: {}", inputs[i].0.display(), e);
+                failed += 1;
+            }
+            if progress {
+                draw_progress(done + 1, inputs.len(), failed);
+            }
+        }
+    });
+    if progress {
+        eprintln!();
+    }
+
+    if failed == 0 {
+        0
+    } else {
+        eprintln!("img convert: {} of {} files failed", failed, inputs.len());
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, out) = (dir.path().join("src"), dir.path().join("out"));
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        image::RgbImage::new(40, 20).save(src.join("a.png")).unwrap();
+        image::RgbImage::new(40, 20).save(src.join("sub").join("a.png")).unwrap();
+        std::fs::write(src.join("broken.jpg"), b"not a jpeg").unwrap();
+
+        let args = ConvertArgs::parse(
+            ["--to", "jpg", "--max-size", "10", "--rotate", "90", "--out"]
+                .iter()
+                .map(|s| s.to_string())
+                .chain([out.to_string_lossy().into_owned(), src.to_string_lossy().into_owned()]),
+        )
+        .unwrap()
+        .unwrap();
+
+        // Both a.png convert into the mirrored tree and broken.jpg fails
+        assert_eq!(run(&args), 1);
+        assert_eq!(image::image_dimensions(out.join("a.jpg")).unwrap(), (5, 10));
+        assert!(out.join("sub").join("a.jpg").exists());
+        assert!(!out.join("broken.jpg").exists());
+
+        // Lossy WebP, as in `--to webp --max-size 1920 --quality 85`
+        let args = ConvertArgs::parse(
+            ["--to", "webp", "--max-size", "10", "--quality", "85", "--out"]
+                .iter()
+                .map(|s| s.to_string())
+                .chain([out.to_string_lossy().into_owned(), src.join("a.png").to_string_lossy().into_owned()]),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(run(&args), 0);
+        assert_eq!(image::image_dimensions(out.join("a.webp")).unwrap(), (10, 5));
+    }
+}
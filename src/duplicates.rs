@@ -0,0 +1,556 @@
+use eframe::egui;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Perceptual hashes of one image. All three are computed together so that
+/// switching algorithms doesn't need another pass.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hashes {
+    pub average: u64,
+    pub difference: u64,
+    pub perceptual: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashKind {
+    Average,
+    Difference,
+    Perceptual,
+}
+
+impl HashKind {
+    pub const ALL: [HashKind; 3] = [HashKind::Average, HashKind::Difference, HashKind::Perceptual];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HashKind::Average => "aHash",
+            HashKind::Difference => "dHash",
+            HashKind::Perceptual => "pHash",
+        }
+    }
+
+    fn pick(self, hashes: &Hashes) -> u64 {
+        match self {
+            HashKind::Average => hashes.average,
+            HashKind::Difference => hashes.difference,
+            HashKind::Perceptual => hashes.perceptual,
+        }
+    }
+}
+
+fn grayscale(img: &DynamicImage, w: u32, h: u32) -> Vec<f32> {
+    img.resize_exact(w, h, image::imageops::FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|p| p.0[0] as f32)
+        .collect()
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.take(64).enumerate().fold(0, |hash, (i, set)| if set { hash | (1 << i) } else { hash })
+}
+
+impl Hashes {
+    pub fn compute(img: &DynamicImage) -> Self {
+        // aHash: 8x8 pixels compared to their mean
+        let small = grayscale(img, 8, 8);
+        let mean = small.iter().sum::<f32>() / small.len() as f32;
+        let average = bits(small.iter().map(|&v| v > mean));
+
+        // dHash: horizontal gradient over 9x8 pixels
+        let wide = grayscale(img, 9, 8);
+        let difference = bits(wide.chunks(9).flat_map(|row| row.windows(2).map(|pair| pair[0] < pair[1])));
+
+        // pHash: low frequencies of a 32x32 DCT compared to their median
+        let dct = dct_2d(&grayscale(img, 32, 32), 32);
+        let low: Vec<f32> = (0..8).flat_map(|y| dct[y * 32..y * 32 + 8].iter().copied()).collect();
+        let mut sorted: Vec<f32> = low[1..].to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        let perceptual = bits(low.iter().map(|&v| v > median));
+
+        Self {
+            average,
+            difference,
+            perceptual,
+        }
+    }
+}
+
+/// Separable DCT-II of an `n`x`n` block.
+fn dct_2d(input: &[f32], n: usize) -> Vec<f32> {
+    let cos: Vec<f32> = (0..n * n)
+        .map(|i| {
+            let (k, x) = (i / n, i % n);
+            (std::f32::consts::PI / n as f32 * (x as f32 + 0.5) * k as f32).cos()
+        })
+        .collect();
+    let dct_1d = |get: &dyn Fn(usize) -> f32, k: usize| (0..n).map(|x| get(x) * cos[k * n + x]).sum::<f32>();
+
+    let mut rows = vec![0.0; n * n];
+    for y in 0..n {
+        for k in 0..n {
+            rows[y * n + k] = dct_1d(&|x| input[y * n + x], k);
+        }
+    }
+    let mut out = vec![0.0; n * n];
+    for x in 0..n {
+        for k in 0..n {
+            out[k * n + x] = dct_1d(&|y| rows[y * n + x], k);
+        }
+    }
+    out
+}
+
+/// Group paths whose hashes are within `threshold` bits of each other
+/// (single linkage). Only groups of two or more are returned.
+pub fn clusters(entries: &[(PathBuf, u64)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    let mut parent: Vec<usize> = (0..entries.len()).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            if (entries[i].1 ^ entries[j].1).count_ones() <= threshold {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                if a != b {
+                    parent[b] = a;
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (i, (path, _)) in entries.iter().enumerate() {
+        groups.entry(find(&mut parent, i)).or_default().push(path.clone());
+    }
+    let mut groups: Vec<Vec<PathBuf>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+    groups
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredHashes {
+    modified: u64,
+    size: u64,
+    hashes: Hashes,
+}
+
+/// Hashes saved in `$XDG_CACHE_HOME/img/hashes.json`, keyed by absolute
+/// path and invalidated when the file's size or mtime changes.
+#[derive(Default, Serialize, Deserialize)]
+pub struct HashStore {
+    entries: HashMap<PathBuf, StoredHashes>,
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let modified = meta.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    Some((modified, meta.len()))
+}
+
+impl HashStore {
+    fn path() -> Option<PathBuf> {
+        let cache = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache.join("img").join("hashes.json"))
+    }
+
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = Self::path().ok_or_else(|| std::io::Error::other("no cache directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        // Write to a temporary file first so a crash can't leave it truncated
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(temp, path)
+    }
+
+    fn key(path: &Path) -> PathBuf {
+        std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Hashes> {
+        let stored = self.entries.get(&Self::key(path))?;
+        let (modified, size) = file_stamp(path)?;
+        (stored.modified == modified && stored.size == size).then_some(stored.hashes)
+    }
+
+    pub fn insert(&mut self, path: &Path, hashes: Hashes) {
+        if let Some((modified, size)) = file_stamp(path) {
+            self.entries.insert(Self::key(path), StoredHashes { modified, size, hashes });
+        }
+    }
+}
+
+/// What the user picked in the duplicates view.
+pub enum Action {
+    /// Show this image in the normal viewer
+    Open(PathBuf),
+    /// Move these images to the trash
+    Trash(Vec<PathBuf>),
+    Close,
+}
+
+const THUMBNAIL_SIZE: u32 = 160;
+
+/// How often clusters are rebuilt while hashes are still coming in, since
+/// clustering compares every pair.
+const CLUSTER_INTERVAL: Duration = Duration::from_millis(500);
+
+// Settings clusters are computed with: hash kind, threshold and hash count
+type ClusterSettings = (HashKind, u32, usize);
+
+struct Thumbnail {
+    path: PathBuf,
+    image: egui::ColorImage,
+    dimensions: (u32, u32),
+}
+
+/// Background hashing of the image list and the cluster view built on it.
+pub struct DuplicateFinder {
+    store: HashStore,
+    hashes: HashMap<PathBuf, Hashes>,
+    total: usize,
+    results: Receiver<(PathBuf, Option<Hashes>)>,
+    hashing: bool,
+    cancel: Arc<AtomicBool>,
+    kind: HashKind,
+    threshold: u32,
+    clusters: Vec<Vec<PathBuf>>,
+    // Settings the clusters were computed with, and when
+    clustered_with: Option<ClusterSettings>,
+    clustered_at: Instant,
+    // Clustering runs on its own thread; the shown clusters stay until it's done
+    clustering: bool,
+    cluster_tx: Sender<(ClusterSettings, Vec<Vec<PathBuf>>)>,
+    cluster_rx: Receiver<(ClusterSettings, Vec<Vec<PathBuf>>)>,
+    thumbnail_tx: Sender<Thumbnail>,
+    thumbnail_rx: Receiver<Thumbnail>,
+    // Thumbnail texture and full image size
+    thumbnails: HashMap<PathBuf, (egui::TextureHandle, (u32, u32))>,
+    thumbnails_requested: HashSet<PathBuf>,
+    ctx: egui::Context,
+    /// Failure to save the hashes, for the viewer's error log
+    error: Option<String>,
+}
+
+impl DuplicateFinder {
+    /// Start hashing `paths`, reusing stored hashes for unchanged files.
+    pub fn start(paths: &[PathBuf], ctx: &egui::Context) -> Self {
+        let store = HashStore::load();
+        let mut hashes = HashMap::new();
+        let mut todo = Vec::new();
+        for path in paths {
+            match store.get(path) {
+                Some(stored) => {
+                    hashes.insert(path.clone(), stored);
+                }
+                None => todo.push(path.clone()),
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let todo = Arc::new(todo);
+        let next = Arc::new(AtomicUsize::new(0));
+        let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        for _ in 0..workers.min(todo.len()) {
+            let (tx, cancel, todo, next, ctx) = (tx.clone(), cancel.clone(), todo.clone(), next.clone(), ctx.clone());
+            std::thread::spawn(move || {
+                while !cancel.load(Ordering::Relaxed) {
+                    let Some(path) = todo.get(next.fetch_add(1, Ordering::Relaxed)) else { break };
+                    let hashes = image::open(path).ok().map(|img| Hashes::compute(&img));
+                    if tx.send((path.clone(), hashes)).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+            });
+        }
+
+        let (thumbnail_tx, thumbnail_rx) = mpsc::channel();
+        let (cluster_tx, cluster_rx) = mpsc::channel();
+        Self {
+            store,
+            hashing: !todo.is_empty(),
+            total: paths.len(),
+            hashes,
+            results: rx,
+            cancel,
+            kind: HashKind::Difference,
+            threshold: 6,
+            clusters: Vec::new(),
+            clustered_with: None,
+            clustered_at: Instant::now(),
+            clustering: false,
+            cluster_tx,
+            cluster_rx,
+            thumbnail_tx,
+            thumbnail_rx,
+            thumbnails: HashMap::new(),
+            thumbnails_requested: HashSet::new(),
+            ctx: ctx.clone(),
+            error: None,
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    /// Collect finished hashes and clusters, and start rebuilding clusters
+    /// when anything changed, at most every [`CLUSTER_INTERVAL`] while hashing.
+    fn poll(&mut self) {
+        let mut failed = 0;
+        loop {
+            match self.results.try_recv() {
+                Ok((path, Some(hashes))) => {
+                    self.store.insert(&path, hashes);
+                    self.hashes.insert(path, hashes);
+                }
+                Ok((_, None)) => failed += 1,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    if self.hashing {
+                        self.hashing = false;
+                        if let Err(e) = self.store.save() {
+                            self.error = Some(format!("Failed to save image hashes: {}", e));
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+        // Undecodable files count towards progress but have no hash
+        self.total = self.total.saturating_sub(failed);
+
+        if let Ok((settings, mut clusters)) = self.cluster_rx.try_recv() {
+            // Images removed while clustering ran are dropped here
+            for cluster in &mut clusters {
+                cluster.retain(|p| self.hashes.contains_key(p));
+            }
+            clusters.retain(|c| c.len() > 1);
+            self.clusters = clusters;
+            self.clustered_with = Some(settings);
+            self.clustering = false;
+            self.request_thumbnails();
+        }
+
+        let settings = (self.kind, self.threshold, self.hashes.len());
+        let changed = self.clustered_with.map(|(kind, threshold, _)| (kind, threshold)) != Some((self.kind, self.threshold));
+        let due = !self.hashing || self.clustered_at.elapsed() >= CLUSTER_INTERVAL;
+        if self.clustering || self.clustered_with == Some(settings) {
+            // Nothing to do, or the running pass is picked up when it's done
+        } else if !changed && !due {
+            // New hashes are picked up once the interval is over
+            self.ctx.request_repaint_after(CLUSTER_INTERVAL - self.clustered_at.elapsed());
+        } else {
+            let entries: Vec<(PathBuf, u64)> = self.hashes.iter().map(|(p, h)| (p.clone(), self.kind.pick(h))).collect();
+            let (tx, ctx, threshold) = (self.cluster_tx.clone(), self.ctx.clone(), self.threshold);
+            std::thread::spawn(move || {
+                let mut entries = entries;
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                if tx.send((settings, clusters(&entries, threshold))).is_ok() {
+                    ctx.request_repaint();
+                }
+            });
+            self.clustering = true;
+            self.clustered_at = Instant::now();
+        }
+
+        while let Ok(thumbnail) = self.thumbnail_rx.try_recv() {
+            let name = format!("duplicate_{}", thumbnail.path.display());
+            let texture = self.ctx.load_texture(name, thumbnail.image, Default::default());
+            self.thumbnails.insert(thumbnail.path, (texture, thumbnail.dimensions));
+        }
+    }
+
+    fn request_thumbnails(&mut self) {
+        let wanted: Vec<PathBuf> = self
+            .clusters
+            .iter()
+            .flatten()
+            .filter(|p| self.thumbnails_requested.insert((*p).clone()))
+            .cloned()
+            .collect();
+        if wanted.is_empty() {
+            return;
+        }
+        let (tx, cancel, ctx) = (self.thumbnail_tx.clone(), self.cancel.clone(), self.ctx.clone());
+        std::thread::spawn(move || {
+            for path in wanted {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(img) = image::open(&path) else { continue };
+                let dimensions = (img.width(), img.height());
+                let thumb = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+                let (w, h) = thumb.dimensions();
+                let image = egui::ColorImage::from_rgba_unmultiplied([w as usize, h as usize], &thumb);
+                if tx.send(Thumbnail { path, image, dimensions }).is_err() {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Forget images that no longer exist.
+    pub fn remove(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.hashes.remove(path);
+            self.thumbnails.remove(path);
+            self.total = self.total.saturating_sub(1);
+        }
+        // Hide them at once rather than when the new clusters arrive
+        for cluster in &mut self.clusters {
+            cluster.retain(|p| !paths.contains(p));
+        }
+        self.clusters.retain(|c| c.len() > 1);
+        self.clustered_with = None;
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<Action> {
+        self.poll();
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            ui.heading("Duplicates");
+            if self.hashing {
+                let done = self.hashes.len() as f32 / self.total.max(1) as f32;
+                ui.add(
+                    egui::ProgressBar::new(done)
+                        .desired_width(200.0)
+                        .text(format!("Hashing {}/{}", self.hashes.len(), self.total)),
+                );
+            }
+            ui.separator();
+            for kind in HashKind::ALL {
+                ui.selectable_value(&mut self.kind, kind, kind.label());
+            }
+            ui.separator();
+            ui.add(egui::Slider::new(&mut self.threshold, 0..=24).text("max distance"));
+            ui.separator();
+            ui.label(format!("{} clusters", self.clusters.len()));
+            if ui.button("Close").clicked() {
+                action = Some(Action::Close);
+            }
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, cluster) in self.clusters.iter().enumerate() {
+                ui.label(format!("Cluster {} ({} images)", i + 1, cluster.len()));
+                egui::ScrollArea::horizontal().id_source(("cluster", i)).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        for path in cluster {
+                            ui.vertical(|ui| {
+                                ui.set_width(THUMBNAIL_SIZE as f32);
+                                let size = egui::vec2(THUMBNAIL_SIZE as f32, THUMBNAIL_SIZE as f32);
+                                let thumbnail = self.thumbnails.get(path);
+                                let response = match thumbnail {
+                                    Some((texture, _)) => ui.add(
+                                        egui::Image::new((texture.id(), texture.size_vec2()))
+                                            .max_size(size)
+                                            .sense(egui::Sense::click()),
+                                    ),
+                                    None => ui.add_sized(size, egui::Spinner::new()),
+                                };
+                                if response.on_hover_text(path.display().to_string()).clicked() {
+                                    action = Some(Action::Open(path.clone()));
+                                }
+                                ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                                if let Some((_, (w, h))) = thumbnail {
+                                    ui.small(format!("{}x{}", w, h));
+                                }
+                                if ui.button("Keep only this").clicked() {
+                                    let others = cluster.iter().filter(|p| *p != path).cloned().collect();
+                                    action = Some(Action::Trash(others));
+                                }
+                            });
+                        }
+                    });
+                });
+                ui.separator();
+            }
+            if self.clusters.is_empty() && !self.hashing && !self.clustering {
+                ui.label("No duplicates found at this distance.");
+            }
+        });
+
+        action
+    }
+}
+
+impl Drop for DuplicateFinder {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        if self.hashing {
+            // Keep what has been hashed so far
+            let _ = self.store.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(w: u32, h: u32, invert: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(w, h, |x, y| {
+            let (u, v) = (x as f32 / w as f32, y as f32 / h as f32);
+            let value = 127.0 + 60.0 * (6.0 * u).sin() + 40.0 * (9.0 * v).cos() + 25.0 * (5.0 * (u + v)).sin();
+            let value = if invert { 255.0 - value } else { value };
+            image::Rgb([value as u8; 3])
+        }))
+    }
+
+    #[test]
+    fn test_hashes_survive_resizing() {
+        let big = Hashes::compute(&pattern(400, 300, false));
+        let small = Hashes::compute(&pattern(200, 150, false));
+        let inverted = Hashes::compute(&pattern(400, 300, true));
+
+        assert!((big.difference ^ small.difference).count_ones() <= 4);
+        assert!((big.perceptual ^ small.perceptual).count_ones() <= 4);
+        assert!((big.average ^ small.average).count_ones() <= 4);
+        assert!((big.perceptual ^ inverted.perceptual).count_ones() > 32);
+    }
+
+    #[test]
+    fn test_clusters() {
+        let entries = vec![
+            (PathBuf::from("a"), 0b0000),
+            (PathBuf::from("b"), 0b0001),
+            (PathBuf::from("c"), 0b0011),
+            (PathBuf::from("d"), u64::MAX),
+        ];
+        assert_eq!(
+            clusters(&entries, 1),
+            vec![vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")]]
+        );
+        assert!(clusters(&entries, 0).is_empty());
+    }
+}
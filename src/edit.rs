@@ -0,0 +1,488 @@
+use crate::adjust::Adjustments;
+use crate::export::{self, EncodeOptions, Format, PngCompression};
+use eframe::egui;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Tonal adjustments, crop, flip and resize on top of the viewer's
+/// rotation, applied in the order: adjust, rotate, flip, crop, resize.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Edits {
+    pub adjustments: Adjustments,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Crop as fractions of the rotated and flipped image
+    pub crop: Option<egui::Rect>,
+    pub resize: Option<(u32, u32, FilterType)>,
+}
+
+impl Edits {
+    pub fn apply(&self, image: &DynamicImage, rotation: u32) -> DynamicImage {
+        let mut out = crate::rotate_image(&self.adjustments.apply(image), rotation);
+        if self.flip_h {
+            out = out.fliph();
+        }
+        if self.flip_v {
+            out = out.flipv();
+        }
+        if let Some(crop) = self.crop {
+            let (x, y, w, h) = crop_pixels(crop, out.dimensions());
+            out = out.crop_imm(x, y, w, h);
+        }
+        if let Some((w, h, filter)) = self.resize {
+            out = out.resize_exact(w, h, filter);
+        }
+        out
+    }
+}
+
+/// Pixel rectangle `(x, y, width, height)` of a fractional crop of an
+/// image of `size`, at least one pixel across.
+pub fn crop_pixels(crop: egui::Rect, size: (u32, u32)) -> (u32, u32, u32, u32) {
+    let (w, h) = (size.0 as f32, size.1 as f32);
+    let x = ((crop.min.x * w).round() as u32).min(size.0.saturating_sub(1));
+    let y = ((crop.min.y * h).round() as u32).min(size.1.saturating_sub(1));
+    let right = ((crop.max.x * w).round() as u32).clamp(x + 1, size.0);
+    let bottom = ((crop.max.y * h).round() as u32).clamp(y + 1, size.1);
+    (x, y, right - x, bottom - y)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aspect {
+    Free,
+    Original,
+    Ratio(u32, u32),
+}
+
+impl Aspect {
+    const PRESETS: [Aspect; 6] = [
+        Aspect::Free,
+        Aspect::Original,
+        Aspect::Ratio(1, 1),
+        Aspect::Ratio(4, 3),
+        Aspect::Ratio(3, 2),
+        Aspect::Ratio(16, 9),
+    ];
+
+    fn label(self) -> String {
+        match self {
+            Aspect::Free => "Free".to_string(),
+            Aspect::Original => "Original".to_string(),
+            Aspect::Ratio(w, h) => format!("{}:{}", w, h),
+        }
+    }
+
+    /// Width over height, for a crop of an image of `size`.
+    fn ratio(self, size: (u32, u32)) -> Option<f32> {
+        match self {
+            Aspect::Free => None,
+            Aspect::Original => Some(size.0 as f32 / size.1 as f32),
+            Aspect::Ratio(w, h) => Some(w as f32 / h as f32),
+        }
+    }
+}
+
+const RESIZE_FILTERS: [(FilterType, &str); 5] = [
+    (FilterType::Nearest, "Nearest"),
+    (FilterType::Triangle, "Linear"),
+    (FilterType::CatmullRom, "Cubic"),
+    (FilterType::Gaussian, "Gaussian"),
+    (FilterType::Lanczos3, "Lanczos"),
+];
+
+pub enum EditAction {
+    Close,
+    Saved(PathBuf),
+}
+
+/// Edit mode for one image: a preview with crop handles and a side panel
+/// of edit and export settings. The edits are applied to a fresh
+/// full-resolution decode when saving.
+pub struct Editor {
+    path: PathBuf,
+    display: DynamicImage,
+    rotation: u32,
+    original_size: (u32, u32),
+    /// Adjustments from the viewer, baked into the saved copy if `bake` is set
+    adjustments: Adjustments,
+    bake: bool,
+    edits: Edits,
+    aspect: Aspect,
+    drag_start: Option<egui::Pos2>,
+    /// Preview texture with the edits it was made with
+    texture: Option<(Edits, egui::TextureHandle)>,
+    resize: bool,
+    resize_size: (u32, u32),
+    keep_aspect: bool,
+    resize_filter: FilterType,
+    save_path: String,
+    encode: EncodeOptions,
+    /// Save was pressed once for an existing file
+    confirm_overwrite: bool,
+    saving: Option<Receiver<Result<PathBuf, String>>>,
+    message: Option<String>,
+}
+
+impl Editor {
+    pub fn new(
+        path: PathBuf,
+        display: DynamicImage,
+        rotation: u32,
+        original_size: (u32, u32),
+        adjustments: Adjustments,
+    ) -> Self {
+        let format = Format::from_path(&path).unwrap_or(Format::Png);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let save_path = path.with_file_name(format!("{}_edited.{}", stem, format.extension()));
+        let mut editor = Self {
+            display,
+            rotation,
+            original_size,
+            adjustments,
+            bake: !adjustments.is_identity(),
+            edits: Edits::default(),
+            aspect: Aspect::Free,
+            drag_start: None,
+            texture: None,
+            resize: false,
+            resize_size: (0, 0),
+            keep_aspect: true,
+            resize_filter: FilterType::Lanczos3,
+            save_path: save_path.to_string_lossy().into_owned(),
+            encode: EncodeOptions::new(format),
+            confirm_overwrite: false,
+            saving: None,
+            message: None,
+            path,
+        };
+        editor.resize_size = editor.cropped_size();
+        editor
+    }
+
+    /// Size of the original after rotation.
+    fn frame_size(&self) -> (u32, u32) {
+        let (w, h) = self.original_size;
+        if self.rotation % 180 == 90 { (h, w) } else { (w, h) }
+    }
+
+    /// Size of the original after rotation and crop.
+    fn cropped_size(&self) -> (u32, u32) {
+        match self.edits.crop {
+            Some(crop) => {
+                let (_, _, w, h) = crop_pixels(crop, self.frame_size());
+                (w, h)
+            }
+            None => self.frame_size(),
+        }
+    }
+
+    fn save(&mut self, ctx: &egui::Context) {
+        let target = PathBuf::from(self.save_path.trim());
+        match Format::from_path(&target) {
+            Some(format) => self.encode.format = format,
+            None => {
+                self.message = Some("Unsupported extension (use .png, .jpg or .webp)".to_string());
+                return;
+            }
+        }
+        if target.exists() && !self.confirm_overwrite {
+            self.confirm_overwrite = true;
+            let what = if same_file(&target, &self.path) { "This is the original" } else { "File exists" };
+            self.message = Some(format!("{}. Press Save again to overwrite it.", what));
+            return;
+        }
+        self.confirm_overwrite = false;
+
+        let mut edits = self.edits.clone();
+        edits.adjustments = if self.bake { self.adjustments } else { Adjustments::default() };
+        edits.resize = self.resize.then_some((self.resize_size.0.max(1), self.resize_size.1.max(1), self.resize_filter));
+        let (source, rotation, options) = (self.path.clone(), self.rotation, self.encode);
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let result = image::open(&source)
+                .map_err(|e| e.to_string())
+                .and_then(|image| export::save(&edits.apply(&image, rotation), &target, &options))
+                .map(|()| target);
+            let _ = tx.send(result);
+            ctx.request_repaint();
+        });
+        self.saving = Some(rx);
+        self.message = Some("Saving...".to_string());
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<EditAction> {
+        let mut action = None;
+        if let Some(rx) = &self.saving
+            && let Ok(result) = rx.try_recv()
+        {
+            self.saving = None;
+            match result {
+                Ok(path) => {
+                    self.message = Some(format!("Saved {}", path.display()));
+                    action = Some(EditAction::Saved(path));
+                }
+                Err(e) => self.message = Some(format!("Save failed: {}", e)),
+            }
+        }
+
+        egui::SidePanel::right("edit_controls")
+            .resizable(false)
+            .show_inside(ui, |ui| {
+                if self.controls(ui) {
+                    action = Some(EditAction::Close);
+                }
+            });
+        egui::CentralPanel::default().show_inside(ui, |ui| self.preview(ui));
+        action
+    }
+
+    /// Side panel; returns true when the editor should close.
+    fn controls(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut close = false;
+        ui.heading("Edit");
+        ui.label(self.path.file_name().unwrap_or_default().to_string_lossy());
+        ui.separator();
+
+        if !self.adjustments.is_identity() {
+            ui.checkbox(&mut self.bake, "Apply adjustments");
+        }
+        ui.horizontal(|ui| {
+            ui.toggle_value(&mut self.edits.flip_h, "Flip ↔");
+            ui.toggle_value(&mut self.edits.flip_v, "Flip ↕");
+        });
+
+        ui.label("Crop (drag on the image)");
+        ui.horizontal_wrapped(|ui| {
+            for aspect in Aspect::PRESETS {
+                ui.selectable_value(&mut self.aspect, aspect, aspect.label());
+            }
+        });
+        let (cw, ch) = self.cropped_size();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} × {}", cw, ch));
+            if self.edits.crop.is_some() && ui.button("Clear crop").clicked() {
+                self.edits.crop = None;
+            }
+        });
+        ui.separator();
+
+        ui.checkbox(&mut self.resize, "Resize");
+        if !self.resize {
+            self.resize_size = (cw, ch);
+        }
+        ui.add_enabled_ui(self.resize, |ui| {
+            let (mut w, mut h) = self.resize_size;
+            ui.horizontal(|ui| {
+                let w_changed = ui.add(egui::DragValue::new(&mut w).clamp_range(1..=65535)).changed();
+                ui.label("×");
+                let h_changed = ui.add(egui::DragValue::new(&mut h).clamp_range(1..=65535)).changed();
+                if self.keep_aspect && w_changed {
+                    h = ((w as f32 * ch as f32 / cw as f32).round() as u32).max(1);
+                } else if self.keep_aspect && h_changed {
+                    w = ((h as f32 * cw as f32 / ch as f32).round() as u32).max(1);
+                }
+            });
+            self.resize_size = (w, h);
+            ui.checkbox(&mut self.keep_aspect, "Keep aspect ratio");
+            let selected = RESIZE_FILTERS.iter().find(|(f, _)| *f == self.resize_filter).map_or("", |(_, name)| name);
+            egui::ComboBox::from_label("Filter").selected_text(selected).show_ui(ui, |ui| {
+                for (filter, name) in RESIZE_FILTERS {
+                    ui.selectable_value(&mut self.resize_filter, filter, name);
+                }
+            });
+        });
+        ui.separator();
+
+        ui.label("Save as");
+        if ui.text_edit_singleline(&mut self.save_path).changed() {
+            self.confirm_overwrite = false;
+            if let Some(format) = Format::from_path(Path::new(self.save_path.trim())) {
+                self.encode.format = format;
+            }
+        }
+        let previous = self.encode.format;
+        egui::ComboBox::from_label("Format")
+            .selected_text(self.encode.format.label())
+            .show_ui(ui, |ui| {
+                for format in Format::ALL {
+                    ui.selectable_value(&mut self.encode.format, format, format.label());
+                }
+            });
+        if self.encode.format != previous {
+            let path = PathBuf::from(self.save_path.trim()).with_extension(self.encode.format.extension());
+            self.save_path = path.to_string_lossy().into_owned();
+            self.confirm_overwrite = false;
+        }
+        match self.encode.format {
+            Format::Jpeg => {
+                ui.add(egui::Slider::new(&mut self.encode.jpeg_quality, 1..=100).text("Quality"));
+            }
+            Format::Png => {
+                ui.horizontal(|ui| {
+                    ui.label("Compression");
+                    for compression in PngCompression::ALL {
+                        ui.selectable_value(&mut self.encode.png_compression, compression, compression.label());
+                    }
+                });
+            }
+            Format::WebP => {
+                let mut lossless = self.encode.webp_quality.is_none();
+                if ui.checkbox(&mut lossless, "Lossless").changed() {
+                    self.encode.webp_quality = if lossless { None } else { Some(self.encode.jpeg_quality) };
+                }
+                if let Some(quality) = &mut self.encode.webp_quality {
+                    ui.add(egui::Slider::new(quality, 1..=100).text("Quality"));
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            let label = if self.confirm_overwrite { "Overwrite" } else { "Save" };
+            if ui.add_enabled(self.saving.is_none(), egui::Button::new(label)).clicked() {
+                self.save(ui.ctx());
+            }
+            if ui.button("Close").clicked() {
+                close = true;
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+        close
+    }
+
+    fn preview(&mut self, ui: &mut egui::Ui) {
+        // The crop is drawn over the full frame and resize isn't previewed
+        let edits = Edits {
+            adjustments: if self.bake { self.adjustments } else { Adjustments::default() },
+            flip_h: self.edits.flip_h,
+            flip_v: self.edits.flip_v,
+            ..Default::default()
+        };
+        if !matches!(&self.texture, Some((shown, _)) if *shown == edits) {
+            let preview = edits.apply(&self.display, self.rotation);
+            let image = egui::ColorImage::from_rgba_unmultiplied(
+                [preview.width() as usize, preview.height() as usize],
+                &preview.to_rgba8(),
+            );
+            self.texture = Some((edits, ui.ctx().load_texture("edit_preview", image, Default::default())));
+        }
+        let Some((_, texture)) = &self.texture else { return };
+
+        // Fit the whole frame so the crop can be adjusted
+        let available = ui.available_rect_before_wrap();
+        let frame = self.frame_size();
+        let scale = (available.width() / frame.0 as f32).min(available.height() / frame.1 as f32);
+        let rect = egui::Rect::from_center_size(available.center(), egui::vec2(frame.0 as f32, frame.1 as f32) * scale);
+        let response = ui.allocate_rect(rect, egui::Sense::click_and_drag());
+        let painter = ui.painter();
+        painter.image(
+            texture.id(),
+            rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+
+        if response.drag_started() {
+            self.drag_start = response.interact_pointer_pos().map(|pos| rect.clamp(pos));
+        }
+        if response.dragged()
+            && let (Some(start), Some(pos)) = (self.drag_start, response.interact_pointer_pos())
+        {
+            let crop = constrain(start, rect.clamp(pos), rect, self.aspect.ratio(frame));
+            if crop.width() >= 2.0 && crop.height() >= 2.0 {
+                let to_fraction = |p: egui::Pos2| ((p - rect.min) / rect.size()).to_pos2();
+                self.edits.crop = Some(egui::Rect::from_min_max(to_fraction(crop.min), to_fraction(crop.max)));
+            }
+        }
+        if response.drag_released() {
+            self.drag_start = None;
+        }
+        if response.clicked() {
+            self.edits.crop = None;
+        }
+
+        // Dim everything outside the crop
+        if let Some(crop) = self.edits.crop {
+            let crop = egui::Rect::from_min_max(
+                rect.min + crop.min.to_vec2() * rect.size(),
+                rect.min + crop.max.to_vec2() * rect.size(),
+            );
+            let shade = egui::Color32::from_black_alpha(150);
+            painter.rect_filled(egui::Rect::from_min_max(rect.min, egui::pos2(rect.max.x, crop.min.y)), 0.0, shade);
+            painter.rect_filled(egui::Rect::from_min_max(egui::pos2(rect.min.x, crop.max.y), rect.max), 0.0, shade);
+            painter.rect_filled(
+                egui::Rect::from_min_max(egui::pos2(rect.min.x, crop.min.y), egui::pos2(crop.min.x, crop.max.y)),
+                0.0,
+                shade,
+            );
+            painter.rect_filled(
+                egui::Rect::from_min_max(egui::pos2(crop.max.x, crop.min.y), egui::pos2(rect.max.x, crop.max.y)),
+                0.0,
+                shade,
+            );
+            painter.rect_stroke(crop, 0.0, egui::Stroke::new(1.5, egui::Color32::WHITE));
+        }
+    }
+}
+
+/// The rectangle dragged from `start` to `end`, held to `ratio` (width over
+/// height, turned upright for tall drags) and kept inside `bounds`.
+fn constrain(start: egui::Pos2, end: egui::Pos2, bounds: egui::Rect, ratio: Option<f32>) -> egui::Rect {
+    let Some(ratio) = ratio else {
+        return egui::Rect::from_two_pos(start, end);
+    };
+    let delta = end - start;
+    let ratio = if delta.y.abs() > delta.x.abs() { 1.0 / ratio } else { ratio };
+    let (sx, sy) = (delta.x.signum(), delta.y.signum());
+
+    // Largest size with the ratio that fits both the drag and the bounds
+    let room_x = if sx < 0.0 { start.x - bounds.min.x } else { bounds.max.x - start.x };
+    let room_y = if sy < 0.0 { start.y - bounds.min.y } else { bounds.max.y - start.y };
+    let width = delta.x.abs().max(delta.y.abs() * ratio).min(room_x).min(room_y * ratio);
+    let height = width / ratio;
+    egui::Rect::from_two_pos(start, start + egui::vec2(sx * width, sy * height))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edits_and_crop_constraint() {
+        let mut rgba = image::RgbaImage::new(4, 2);
+        rgba.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        let image = DynamicImage::ImageRgba8(rgba);
+
+        let edits = Edits {
+            flip_h: true,
+            crop: Some(egui::Rect::from_min_max(egui::pos2(0.5, 0.0), egui::pos2(1.0, 0.5))),
+            ..Default::default()
+        };
+        let out = edits.apply(&image, 0);
+        assert_eq!(out.dimensions(), (2, 1));
+        assert_eq!(out.get_pixel(1, 0).0, [255, 0, 0, 255]);
+
+        // Rotated 90°, the red corner ends up top-right, flipped it's top-left
+        let out = Edits { flip_h: true, ..Default::default() }.apply(&image, 90);
+        assert_eq!(out.dimensions(), (2, 4));
+        assert_eq!(out.get_pixel(0, 0).0, [255, 0, 0, 255]);
+
+        let bounds = egui::Rect::from_min_size(egui::pos2(0.0, 0.0), egui::vec2(100.0, 100.0));
+        let square = constrain(egui::pos2(10.0, 10.0), egui::pos2(50.0, 20.0), bounds, Some(1.0));
+        assert_eq!(square, egui::Rect::from_min_max(egui::pos2(10.0, 10.0), egui::pos2(50.0, 50.0)));
+        let clamped = constrain(egui::pos2(80.0, 10.0), egui::pos2(99.0, 90.0), bounds, Some(2.0));
+        assert_eq!(clamped.width(), 20.0);
+        assert_eq!(clamped.height(), 40.0);
+    }
+}
@@ -0,0 +1,105 @@
+use eframe::egui;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Entries kept before the oldest are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+/// Errors from this session, oldest first, for the Ctrl+L panel.
+pub struct ErrorLog {
+    started: Instant,
+    /// Time since the session started, with the message
+    entries: VecDeque<(Duration, String)>,
+    pub open: bool,
+    /// Also print each message to stderr as it comes in
+    pub echo: bool,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            entries: VecDeque::new(),
+            open: false,
+            echo: true,
+        }
+    }
+}
+
+impl ErrorLog {
+    pub fn push(&mut self, message: String) {
+        if self.echo {
+            eprintln!("{}", message);
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((self.started.elapsed(), message));
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(_, message)| message.as_str())
+    }
+
+    /// The panel, with the skip broken files switch since that's where
+    /// people look when broken files get in the way.
+    pub fn show(&mut self, ctx: &egui::Context, skip_broken: &mut bool) {
+        if !self.open {
+            return;
+        }
+        let mut open = true;
+        egui::Window::new("Error log")
+            .open(&mut open)
+            .default_size(egui::vec2(520.0, 300.0))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(skip_broken, "Skip broken files");
+                    if ui.button("Clear").clicked() {
+                        self.entries.clear();
+                    }
+                });
+                ui.separator();
+                if self.entries.is_empty() {
+                    ui.label("No errors");
+                    return;
+                }
+                egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+                    for (at, message) in &self.entries {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.monospace(format_elapsed(*at));
+                            ui.label(message);
+                        });
+                    }
+                });
+            });
+        self.open = open;
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` after the first hour.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 3600 {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    } else {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capped_and_formatted() {
+        let mut log = ErrorLog { echo: false, ..Default::default() };
+        for i in 0..MAX_ENTRIES + 5 {
+            log.push(format!("error {}", i));
+        }
+        assert_eq!(log.entries.len(), MAX_ENTRIES);
+        assert_eq!(log.entries.front().unwrap().1, "error 5");
+
+        assert_eq!(format_elapsed(Duration::from_secs(65)), "1:05");
+        assert_eq!(format_elapsed(Duration::from_secs(3725)), "1:02:05");
+    }
+}
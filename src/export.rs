@@ -0,0 +1,171 @@
+use image::DynamicImage;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use std::io::Write;
+use std::path::Path;
+
+/// Formats that can be written, with their encoder settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Png, Format::Jpeg, Format::WebP];
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "webp" => Some(Format::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::WebP => "webp",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::WebP => "WebP",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub const ALL: [PngCompression; 3] = [PngCompression::Fast, PngCompression::Default, PngCompression::Best];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PngCompression::Fast => "fast",
+            PngCompression::Default => "default",
+            PngCompression::Best => "best",
+        }
+    }
+}
+
+impl std::str::FromStr for PngCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.label() == s)
+            .ok_or_else(|| format!("unknown PNG compression '{}' (fast, default, best)", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncodeOptions {
+    pub format: Format,
+    /// 1-100
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+    /// 1-100 for lossy WebP, `None` for lossless
+    pub webp_quality: Option<u8>,
+}
+
+impl EncodeOptions {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            jpeg_quality: 90,
+            png_compression: PngCompression::Default,
+            webp_quality: None,
+        }
+    }
+}
+
+/// Encode `image` to `path`. The file is written under a temporary name
+/// and renamed into place, so a failed save never leaves a partial file.
+pub fn save(image: &DynamicImage, path: &Path, options: &EncodeOptions) -> Result<(), String> {
+    let name = path.file_name().ok_or("not a file name")?.to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.img-save-{}", name, std::process::id()));
+    let result = write(image, &temp, options).and_then(|()| std::fs::rename(&temp, path).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write(image: &DynamicImage, path: &Path, options: &EncodeOptions) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut out = std::io::BufWriter::new(file);
+    let result = match options.format {
+        Format::Png => {
+            let compression = match options.png_compression {
+                PngCompression::Fast => CompressionType::Fast,
+                PngCompression::Default => CompressionType::Default,
+                PngCompression::Best => CompressionType::Best,
+            };
+            image.write_with_encoder(PngEncoder::new_with_quality(&mut out, compression, PngFilter::Adaptive))
+        }
+        Format::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+            rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut out, options.jpeg_quality.clamp(1, 100)))
+        }
+        Format::WebP => match options.webp_quality {
+            Some(quality) => {
+                // image only encodes lossless WebP, so lossy goes through libwebp
+                let rgba = image.to_rgba8();
+                let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                    .encode_simple(false, quality.clamp(1, 100) as f32)
+                    .map_err(|e| format!("WebP encoding failed: {:?}", e))?;
+                out.write_all(&encoded).map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            None => {
+                let rgba = DynamicImage::ImageRgba8(image.to_rgba8());
+                rgba.write_with_encoder(WebPEncoder::new_lossless(&mut out))
+            }
+        },
+    };
+    result.map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(3, 2, image::Rgba([200, 100, 50, 128])));
+
+        for format in Format::ALL {
+            let path = dir.path().join(format!("out.{}", format.extension()));
+            assert_eq!(Format::from_path(&path), Some(format));
+            save(&image, &path, &EncodeOptions::new(format)).unwrap();
+            assert_eq!(image::image_dimensions(&path).unwrap(), (3, 2));
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        let lossy = dir.path().join("lossy.webp");
+        let options = EncodeOptions { webp_quality: Some(85), ..EncodeOptions::new(Format::WebP) };
+        save(&image, &lossy, &options).unwrap();
+        assert_eq!(image::image_dimensions(&lossy).unwrap(), (3, 2));
+    }
+}
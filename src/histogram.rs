@@ -0,0 +1,241 @@
+use eframe::egui;
+use image::DynamicImage;
+use crate::loader::{self, LoadHandle, Loader};
+use std::path::{Path, PathBuf};
+
+/// Share of pixels at 0 or 255 above which the clipping indicators light up.
+const CLIPPING_WARNING: f32 = 0.005;
+
+/// Which channel of the image to display.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Channel {
+    #[default]
+    All,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    fn index(self) -> Option<usize> {
+        match self {
+            Channel::All => None,
+            Channel::Red => Some(0),
+            Channel::Green => Some(1),
+            Channel::Blue => Some(2),
+            Channel::Alpha => Some(3),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Channel::All => "RGB",
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Alpha => "alpha",
+        }
+    }
+}
+
+/// One channel of `image` as an opaque grayscale image.
+pub fn extract_channel(image: &DynamicImage, channel: Channel) -> DynamicImage {
+    let Some(index) = channel.index() else {
+        return image.clone();
+    };
+    let mut rgba = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let value = pixel.0[index];
+        pixel.0 = [value, value, value, 255];
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Per-channel counts for each 8-bit value, with luminance using Rec. 709
+/// weights.
+pub struct Histogram {
+    pub red: [u32; 256],
+    pub green: [u32; 256],
+    pub blue: [u32; 256],
+    pub luma: [u32; 256],
+    /// Pixels with any channel at 0, and at 255
+    pub shadows_clipped: u64,
+    pub highlights_clipped: u64,
+    pub total: u64,
+}
+
+impl Histogram {
+    pub fn compute(image: &DynamicImage) -> Self {
+        let mut histogram = Histogram {
+            red: [0; 256],
+            green: [0; 256],
+            blue: [0; 256],
+            luma: [0; 256],
+            shadows_clipped: 0,
+            highlights_clipped: 0,
+            total: 0,
+        };
+        for pixel in image.to_rgb8().pixels() {
+            let [r, g, b] = pixel.0;
+            histogram.red[r as usize] += 1;
+            histogram.green[g as usize] += 1;
+            histogram.blue[b as usize] += 1;
+            let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            histogram.luma[luma.round() as usize] += 1;
+            if r == 0 || g == 0 || b == 0 {
+                histogram.shadows_clipped += 1;
+            }
+            if r == 255 || g == 255 || b == 255 {
+                histogram.highlights_clipped += 1;
+            }
+            histogram.total += 1;
+        }
+        histogram
+    }
+
+    fn fraction(&self, count: u64) -> f32 {
+        if self.total == 0 { 0.0 } else { count as f32 / self.total as f32 }
+    }
+}
+
+/// Histogram overlay for the current image. The full-resolution file is
+/// decoded and counted by the loader, behind the images being browsed.
+#[derive(Default)]
+pub struct HistogramOverlay {
+    current: Option<(PathBuf, Option<Histogram>)>,
+    pending: Option<LoadHandle<Histogram>>,
+}
+
+impl HistogramOverlay {
+    fn histogram_for(&mut self, path: &Path, loader: &Loader) -> Option<&Histogram> {
+        if let Some(handle) = &mut self.pending
+            && handle.path() == path
+            && let Some(result) = handle.try_take()
+        {
+            self.current = Some((path.to_path_buf(), result.ok()));
+            self.pending = None;
+        }
+
+        let is_current = matches!(&self.current, Some((current, _)) if current == path);
+        let is_pending = matches!(&self.pending, Some(handle) if handle.path() == path);
+        if !is_current && !is_pending {
+            // Only the image on screen is worth counting
+            if let Some(stale) = self.pending.take() {
+                stale.cancel();
+            }
+            let handle = loader.load_full(path.to_path_buf(), loader::FULL_PRIORITY, |image| Histogram::compute(&image));
+            self.pending = Some(handle);
+        }
+
+        match &self.current {
+            Some((current, histogram)) if current == path => histogram.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Draw the histogram in the top-left corner of `rect`.
+    pub fn show(&mut self, ui: &egui::Ui, rect: egui::Rect, path: &Path, loader: &Loader) {
+        let painter = ui.painter();
+        let frame = egui::Rect::from_min_size(rect.min + egui::vec2(10.0, 10.0), egui::vec2(276.0, 150.0));
+        painter.rect_filled(frame, 4.0, egui::Color32::from_black_alpha(200));
+
+        let Some(histogram) = self.histogram_for(path, loader) else {
+            painter.text(
+                frame.center(),
+                egui::Align2::CENTER_CENTER,
+                "Computing histogram...",
+                egui::FontId::proportional(13.0),
+                egui::Color32::GRAY,
+            );
+            return;
+        };
+
+        let plot = egui::Rect::from_min_size(frame.min + egui::vec2(10.0, 10.0), egui::vec2(256.0, 110.0));
+        let peak = [&histogram.red, &histogram.green, &histogram.blue, &histogram.luma]
+            .iter()
+            .flat_map(|counts| counts.iter())
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1) as f32;
+        let series = [
+            (&histogram.luma, egui::Color32::from_white_alpha(90)),
+            (&histogram.red, egui::Color32::from_rgba_unmultiplied(255, 60, 60, 160)),
+            (&histogram.green, egui::Color32::from_rgba_unmultiplied(60, 255, 60, 160)),
+            (&histogram.blue, egui::Color32::from_rgba_unmultiplied(80, 120, 255, 160)),
+        ];
+        for (counts, color) in series {
+            let points = counts
+                .iter()
+                .enumerate()
+                .map(|(value, &count)| {
+                    // Square root keeps small counts visible next to a tall peak
+                    let height = (count as f32 / peak).sqrt() * plot.height();
+                    egui::pos2(plot.left() + value as f32, plot.bottom() - height)
+                })
+                .collect();
+            painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, color)));
+        }
+
+        // Clipping indicators under each end of the plot
+        let shadows = histogram.fraction(histogram.shadows_clipped);
+        let highlights = histogram.fraction(histogram.highlights_clipped);
+        let indicator = |fraction: f32| {
+            if fraction > CLIPPING_WARNING {
+                egui::Color32::from_rgb(255, 80, 80)
+            } else {
+                egui::Color32::GRAY
+            }
+        };
+        painter.text(
+            plot.left_bottom() + egui::vec2(0.0, 6.0),
+            egui::Align2::LEFT_TOP,
+            format!("◀ shadows {:.1}%", shadows * 100.0),
+            egui::FontId::proportional(12.0),
+            indicator(shadows),
+        );
+        painter.text(
+            plot.right_bottom() + egui::vec2(0.0, 6.0),
+            egui::Align2::RIGHT_TOP,
+            format!("highlights {:.1}% ▶", highlights * 100.0),
+            egui::FontId::proportional(12.0),
+            indicator(highlights),
+        );
+    }
+}
+
+impl Drop for HistogramOverlay {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.pending {
+            pending.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_and_channels() {
+        let mut rgba = image::RgbaImage::new(2, 2);
+        rgba.put_pixel(0, 0, image::Rgba([255, 255, 255, 255]));
+        rgba.put_pixel(1, 0, image::Rgba([0, 0, 0, 255]));
+        rgba.put_pixel(0, 1, image::Rgba([10, 20, 30, 0]));
+        rgba.put_pixel(1, 1, image::Rgba([10, 20, 30, 128]));
+        let image = DynamicImage::ImageRgba8(rgba);
+
+        let histogram = Histogram::compute(&image);
+        assert_eq!(histogram.total, 4);
+        assert_eq!(histogram.red[10], 2);
+        assert_eq!(histogram.luma[255], 1);
+        assert_eq!(histogram.shadows_clipped, 1);
+        assert_eq!(histogram.highlights_clipped, 1);
+
+        let alpha = extract_channel(&image, Channel::Alpha).to_rgba8();
+        assert_eq!(alpha.get_pixel(1, 1).0, [128, 128, 128, 255]);
+        let green = extract_channel(&image, Channel::Green).to_rgba8();
+        assert_eq!(green.get_pixel(0, 1).0, [20, 20, 20, 255]);
+    }
+}
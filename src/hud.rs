@@ -0,0 +1,126 @@
+use crate::loader::LoaderStats;
+use eframe::egui;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Frame times kept for the average and worst case.
+const FRAMES: usize = 120;
+
+/// What the HUD shows besides its own counters.
+pub struct HudInfo {
+    pub loader: LoaderStats,
+    pub cache_len: usize,
+    pub cache_bytes: usize,
+    pub cache_max_bytes: usize,
+    /// Preload handles still in flight
+    pub preloads: usize,
+}
+
+/// The F12 debug overlay, with the counters it needs kept up to date
+/// whether or not it's shown.
+#[derive(Default)]
+pub struct Hud {
+    pub open: bool,
+    /// CPU time of the latest frames
+    frame_times: VecDeque<Duration>,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl Hud {
+    pub fn record_frame(&mut self, cpu_time: Duration) {
+        if self.frame_times.len() == FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(cpu_time);
+    }
+
+    fn lines(&self, info: &HudInfo) -> Vec<String> {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut lines = Vec::new();
+        if let Some(worst) = self.frame_times.iter().max() {
+            let average = self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32;
+            lines.push(format!("frame   {:6.1} ms avg {:6.1} ms max", ms(average), ms(*worst)));
+        }
+        match [0.5, 0.9, 0.99].map(|p| percentile(&info.loader.recent, p)) {
+            [Some(p50), Some(p90), Some(p99)] => lines.push(format!(
+                "decode  {:.0}/{:.0}/{:.0} ms p50/p90/p99 of {}",
+                ms(p50),
+                ms(p90),
+                ms(p99),
+                info.loader.recent.len()
+            )),
+            _ => lines.push("decode  -".to_string()),
+        }
+        lines.push(format!(
+            "cache   {} images, {} / {} MiB",
+            info.cache_len,
+            info.cache_bytes >> 20,
+            info.cache_max_bytes >> 20
+        ));
+        lines.push(format!("        {} hits, {} misses", self.cache_hits, self.cache_misses));
+        lines.push(format!("queue   {} waiting, {} decoding", info.loader.queued, info.loader.running));
+        lines.push(format!("preload {} in flight", info.preloads));
+        lines
+    }
+
+    pub fn show(&self, ctx: &egui::Context, info: &HudInfo) {
+        if !self.open {
+            return;
+        }
+        egui::Area::new("debug_hud")
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for line in self.lines(info) {
+                        ui.monospace(line);
+                    }
+                });
+            });
+        // Keep the numbers live without repainting flat out
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+}
+
+/// The `p` quantile (0 to 1) of `samples`, nearest rank.
+fn percentile(samples: &[Duration], p: f64) -> Option<Duration> {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len().max(1)) - 1;
+    sorted.get(index).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_and_lines() {
+        let samples: Vec<_> = (1..=100).rev().map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&samples, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples[..1], 0.9), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&[], 0.5), None);
+
+        let mut hud = Hud::default();
+        hud.record_frame(Duration::from_millis(2));
+        hud.record_frame(Duration::from_millis(4));
+        let info = HudInfo {
+            loader: LoaderStats {
+                queued: 3,
+                running: 1,
+                recent: samples,
+            },
+            cache_len: 2,
+            cache_bytes: 3 << 20,
+            cache_max_bytes: 1 << 30,
+            preloads: 4,
+        };
+        let lines = hud.lines(&info);
+        assert_eq!(lines[0], "frame      3.0 ms avg    4.0 ms max");
+        assert_eq!(lines[1], "decode  50/90/99 ms p50/p90/p99 of 100");
+        assert_eq!(lines[2], "cache   2 images, 3 / 1024 MiB");
+        assert_eq!(lines[4], "queue   3 waiting, 1 decoding");
+    }
+}
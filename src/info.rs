@@ -0,0 +1,165 @@
+use crate::{metadata, scanner};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, ImageFormat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// EXIF fields worth showing, with the names they're printed under.
+const EXIF_FIELDS: [(exif::Tag, &str); 8] = [
+    (exif::Tag::Make, "make"),
+    (exif::Tag::Model, "model"),
+    (exif::Tag::LensModel, "lens"),
+    (exif::Tag::DateTimeOriginal, "date"),
+    (exif::Tag::ExposureTime, "exposure"),
+    (exif::Tag::FNumber, "aperture"),
+    (exif::Tag::PhotographicSensitivity, "iso"),
+    (exif::Tag::FocalLength, "focal_length"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct ImageInfo {
+    pub path: PathBuf,
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub color: String,
+    /// Bits per channel
+    pub bit_depth: u16,
+    pub frames: usize,
+    pub file_size: u64,
+    /// EXIF orientation, 1-8
+    pub orientation: Option<u32>,
+    pub exif: BTreeMap<String, String>,
+}
+
+/// Everything `img info` reports for `path`. The image is fully decoded,
+/// so any error means the file is unreadable or corrupt.
+pub fn read(path: &Path) -> Result<ImageInfo, String> {
+    let file_size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let reader = image::io::Reader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| e.to_string())?;
+    let format = reader.format().ok_or("unknown format")?;
+    let image = reader.decode().map_err(|e| e.to_string())?;
+    let color = image.color();
+    let frames = frame_count(path, format)?;
+
+    let exif = metadata::read_exif(path);
+    let orientation = exif
+        .as_ref()
+        .and_then(|exif| exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY))
+        .and_then(|field| field.value.get_uint(0));
+    let fields = exif
+        .as_ref()
+        .map(|exif| {
+            EXIF_FIELDS
+                .iter()
+                .filter_map(|&(tag, name)| {
+                    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+                    let value = field.display_value().with_unit(exif).to_string();
+                    Some((name.to_string(), value.trim_matches('"').to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(ImageInfo {
+        path: path.to_path_buf(),
+        format: format!("{:?}", format),
+        width: image.width(),
+        height: image.height(),
+        color: format!("{:?}", color),
+        bit_depth: color.bits_per_pixel() / color.channel_count() as u16,
+        frames,
+        file_size,
+        orientation,
+        exif: fields,
+    })
+}
+
+/// Number of frames, decoding every one of them for animated formats.
+fn frame_count(path: &Path, format: ImageFormat) -> Result<usize, String> {
+    let open = || std::fs::File::open(path).map(BufReader::new).map_err(|e| e.to_string());
+    let count = |mut frames: image::Frames| frames.try_fold(0, |n, frame| frame.map(|_| n + 1));
+    let result = match format {
+        ImageFormat::Gif => GifDecoder::new(open()?).and_then(|d| count(d.into_frames())),
+        ImageFormat::Png => PngDecoder::new(open()?).and_then(|d| if d.is_apng() { count(d.apng().into_frames()) } else { Ok(1) }),
+        ImageFormat::WebP => {
+            WebPDecoder::new(open()?).and_then(|d| if d.has_animation() { count(d.into_frames()) } else { Ok(1) })
+        }
+        _ => Ok(1),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+fn print_table(info: &ImageInfo) {
+    println!("{}", info.path.display());
+    let mut rows = vec![
+        ("format", info.format.clone()),
+        ("dimensions", format!("{} × {}", info.width, info.height)),
+        ("color", format!("{} ({} bits per channel)", info.color, info.bit_depth)),
+        ("frames", info.frames.to_string()),
+        ("file size", format!("{} bytes", info.file_size)),
+    ];
+    if let Some(orientation) = info.orientation {
+        rows.push(("orientation", orientation.to_string()));
+    }
+    rows.extend(info.exif.iter().map(|(name, value)| (name.as_str(), value.clone())));
+    for (name, value) in rows {
+        println!("  {:<13}{}", name, value);
+    }
+}
+
+/// Print info for every image under `paths`. Returns the exit code: 1 if
+/// any file couldn't be decoded.
+pub fn run(paths: &[PathBuf], json: bool) -> i32 {
+    let images = scanner::expand_paths(paths.iter().cloned());
+    let mut failed = 0;
+    let mut entries = Vec::new();
+
+    for path in &images {
+        match read(path) {
+            Ok(info) if json => entries.push(serde_json::to_value(&info).unwrap_or_default()),
+            Ok(info) => print_table(&info),
+            Err(e) => {
+                failed += 1;
+                eprintln!("img info: {}: {}", path.display(), e);
+                if json {
+                    entries.push(serde_json::json!({ "path": path, "error": e }));
+                }
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::Value::Array(entries));
+    }
+    if images.is_empty() {
+        eprintln!("img info: no images found");
+        return 1;
+    }
+    i32::from(failed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.png");
+        image::ImageBuffer::<image::Rgba<u16>, Vec<u16>>::new(3, 2).save(&good).unwrap();
+        let bad = dir.path().join("bad.png");
+        std::fs::write(&bad, &std::fs::read(&good).unwrap()[..20]).unwrap();
+
+        let info = read(&good).unwrap();
+        assert_eq!((info.format.as_str(), info.width, info.height), ("Png", 3, 2));
+        assert_eq!((info.color.as_str(), info.bit_depth, info.frames), ("Rgba16", 16, 1));
+        assert!(read(&bad).is_err());
+        assert_eq!(run(&[dir.path().to_path_buf()], true), 1);
+    }
+}
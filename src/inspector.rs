@@ -0,0 +1,216 @@
+use eframe::egui;
+use image::{DynamicImage, GenericImageView};
+use crate::cache::CachedImage;
+use crate::loader::{self, LoadHandle, Loader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Pixels shown on each side of the one under the cursor in the loupe.
+const LOUPE_RADIUS: i64 = 5;
+const LOUPE_CELL: f32 = 12.0;
+
+/// Map a screen position to a pixel of the original image. `rect` is where
+/// the rotated image is drawn and `size` the unrotated original size.
+pub fn screen_to_image(pos: egui::Pos2, rect: egui::Rect, rotation: u32, size: (u32, u32)) -> Option<(u32, u32)> {
+    let u = (pos.x - rect.min.x) / rect.width();
+    let v = (pos.y - rect.min.y) / rect.height();
+    if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+        return None;
+    }
+    // Undo the clockwise rotation applied for display
+    let (u, v) = match rotation % 360 {
+        90 => (v, 1.0 - u),
+        180 => (1.0 - u, 1.0 - v),
+        270 => (1.0 - v, u),
+        _ => (u, v),
+    };
+    let x = ((u * size.0 as f32) as u32).min(size.0.saturating_sub(1));
+    let y = ((v * size.1 as f32) as u32).min(size.1.saturating_sub(1));
+    Some((x, y))
+}
+
+pub fn hex(rgba: [u8; 4]) -> String {
+    if rgba[3] == 255 {
+        format!("#{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2])
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2], rgba[3])
+    }
+}
+
+/// Hue in degrees, saturation and value in 0..1.
+pub fn hsv(rgba: [u8; 4]) -> (f32, f32, f32) {
+    let [r, g, b] = [rgba[0], rgba[1], rgba[2]].map(|c| c as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+/// Loupe and color readout for the pixel under the cursor. Values come from
+/// a full-resolution decode, queued with the loader when the inspector is
+/// turned on for an image.
+#[derive(Default)]
+pub struct Inspector {
+    /// The decoded original, `None` if it couldn't be decoded
+    original: Option<(PathBuf, Option<Arc<DynamicImage>>)>,
+    pending: Option<LoadHandle<Arc<DynamicImage>>>,
+}
+
+impl Inspector {
+    fn original_for(&mut self, path: &Path, loader: &Loader) -> Option<Arc<DynamicImage>> {
+        if let Some(handle) = &mut self.pending
+            && handle.path() == path
+            && let Some(result) = handle.try_take()
+        {
+            self.original = Some((path.to_path_buf(), result.ok()));
+            self.pending = None;
+        }
+        if let Some((loaded, image)) = &self.original
+            && loaded == path
+        {
+            return image.clone();
+        }
+
+        if !matches!(&self.pending, Some(handle) if handle.path() == path) {
+            // The image was switched before its original arrived
+            if let Some(stale) = self.pending.take() {
+                stale.cancel();
+            }
+            self.pending = Some(loader.load_full(path.to_path_buf(), loader::FULL_PRIORITY, Arc::new));
+        }
+        None
+    }
+
+    /// Draw the loupe next to the pointer when it is over `rect`, where
+    /// `shown` is drawn. Clicking copies the hex color.
+    pub fn show(&mut self, ctx: &egui::Context, rect: egui::Rect, path: &Path, shown: &CachedImage, loader: &Loader) {
+        let (display, rotation, original_size) = (&shown.display_image, shown.rotation, shown.original_size);
+        let Some(pointer) = ctx.input(|i| i.pointer.hover_pos()) else { return };
+        let Some((x, y)) = screen_to_image(pointer, rect, rotation, original_size) else { return };
+
+        // Sample the full-resolution image, or the display copy until it's loaded
+        let original = self.original_for(path, loader);
+        let sample = |sx: i64, sy: i64| -> Option<[u8; 4]> {
+            let (w, h) = original_size;
+            if sx < 0 || sy < 0 || sx >= w as i64 || sy >= h as i64 {
+                return None;
+            }
+            match &original {
+                Some(image) => Some(image.get_pixel(sx as u32, sy as u32).0),
+                None => {
+                    let (dw, dh) = display.dimensions();
+                    let dx = (sx as u64 * dw as u64 / w as u64) as u32;
+                    let dy = (sy as u64 * dh as u64 / h as u64) as u32;
+                    Some(display.get_pixel(dx.min(dw - 1), dy.min(dh - 1)).0)
+                }
+            }
+        };
+        let Some(rgba) = sample(x as i64, y as i64) else { return };
+
+        if ctx.input(|i| i.pointer.primary_clicked()) {
+            ctx.output_mut(|o| o.copied_text = hex(rgba));
+        }
+
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Tooltip, egui::Id::new("pixel_inspector")));
+        let side = (2 * LOUPE_RADIUS + 1) as f32 * LOUPE_CELL;
+        let mut origin = pointer + egui::vec2(24.0, 24.0);
+        let screen = ctx.screen_rect();
+        if origin.x + side + 220.0 > screen.right() {
+            origin.x = pointer.x - side - 24.0;
+        }
+        if origin.y + side + 90.0 > screen.bottom() {
+            origin.y = pointer.y - side - 114.0;
+        }
+
+        // Magnified neighborhood in original-image orientation
+        let loupe = egui::Rect::from_min_size(origin, egui::vec2(side, side));
+        painter.rect_filled(loupe.expand(2.0), 2.0, egui::Color32::BLACK);
+        for dy in -LOUPE_RADIUS..=LOUPE_RADIUS {
+            for dx in -LOUPE_RADIUS..=LOUPE_RADIUS {
+                let cell = egui::Rect::from_min_size(
+                    origin + egui::vec2((dx + LOUPE_RADIUS) as f32, (dy + LOUPE_RADIUS) as f32) * LOUPE_CELL,
+                    egui::vec2(LOUPE_CELL, LOUPE_CELL),
+                );
+                let color = match sample(x as i64 + dx, y as i64 + dy) {
+                    Some([r, g, b, a]) => egui::Color32::from_rgba_unmultiplied(r, g, b, a),
+                    None => egui::Color32::from_gray(40),
+                };
+                painter.rect_filled(cell, 0.0, color);
+            }
+        }
+        let center = egui::Rect::from_min_size(
+            origin + egui::vec2(LOUPE_RADIUS as f32, LOUPE_RADIUS as f32) * LOUPE_CELL,
+            egui::vec2(LOUPE_CELL, LOUPE_CELL),
+        );
+        painter.rect_stroke(center, 0.0, egui::Stroke::new(1.5, egui::Color32::WHITE));
+
+        let (h, s, v) = hsv(rgba);
+        let [r, g, b, a] = rgba;
+        let text = format!(
+            "x {}  y {}{}\nRGBA {} {} {} {}\nfloat {:.3} {:.3} {:.3} {:.3}\nhex {}   HSV {:.0}° {:.0}% {:.0}%\nalpha {:.1}%   click to copy hex",
+            x,
+            y,
+            if original.is_none() { "  (approx.)" } else { "" },
+            r,
+            g,
+            b,
+            a,
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+            hex(rgba),
+            h,
+            s * 100.0,
+            v * 100.0,
+            a as f32 / 255.0 * 100.0,
+        );
+        let galley = painter.layout_no_wrap(text, egui::FontId::monospace(12.0), egui::Color32::WHITE);
+        let text_rect = egui::Rect::from_min_size(loupe.left_bottom() + egui::vec2(0.0, 6.0), galley.size()).expand(4.0);
+        painter.rect_filled(text_rect, 2.0, egui::Color32::from_black_alpha(220));
+        painter.galley(text_rect.min + egui::vec2(4.0, 4.0), galley, egui::Color32::WHITE);
+    }
+}
+
+impl Drop for Inspector {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.pending {
+            pending.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_to_image_with_rotation() {
+        // A 400x200 image drawn rotated 90° into a 100x200 rect
+        let rect = egui::Rect::from_min_size(egui::pos2(10.0, 20.0), egui::vec2(100.0, 200.0));
+        let top_right = egui::pos2(109.0, 20.5);
+        assert_eq!(screen_to_image(top_right, rect, 90, (400, 200)), Some((1, 1)));
+        assert_eq!(screen_to_image(top_right, rect, 270, (400, 200)), Some((399, 198)));
+        assert_eq!(screen_to_image(egui::pos2(9.0, 30.0), rect, 0, (400, 200)), None);
+
+        let rect = egui::Rect::from_min_size(egui::pos2(0.0, 0.0), egui::vec2(400.0, 200.0));
+        assert_eq!(screen_to_image(egui::pos2(0.5, 0.5), rect, 180, (400, 200)), Some((399, 199)));
+    }
+
+    #[test]
+    fn test_color_formats() {
+        assert_eq!(hex([255, 128, 0, 255]), "#ff8000");
+        assert_eq!(hex([255, 128, 0, 16]), "#ff800010");
+        let (h, s, v) = hsv([0, 0, 255, 255]);
+        assert_eq!((h, s, v), (240.0, 1.0, 1.0));
+    }
+}
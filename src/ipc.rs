@@ -0,0 +1,236 @@
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// A command received over the control socket, one JSON object per line,
+/// e.g. `{"cmd": "goto", "index": 3}` or `{"cmd": "open", "path": "a.png"}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Command {
+    Open { path: PathBuf },
+    Next,
+    Prev,
+    Goto { index: usize },
+    Rotate,
+    /// Zoom to `factor`, or back to fit-to-window when omitted.
+    Zoom { factor: Option<f32> },
+    GetCurrent,
+    List,
+}
+
+/// A parsed command waiting to be executed on the UI thread.
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Value>,
+}
+
+impl Request {
+    pub fn respond(self, value: Value) {
+        // The client may already have disconnected
+        let _ = self.reply.send(value);
+    }
+}
+
+type Client = Arc<Mutex<UnixStream>>;
+
+pub struct IpcServer {
+    path: PathBuf,
+    requests: Receiver<Request>,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl IpcServer {
+    /// Socket path for this process: `$XDG_RUNTIME_DIR/img-<pid>.sock`, or
+    /// `$TMPDIR/img-<uid>/img-<pid>.sock` without a runtime directory.
+    pub fn default_path() -> std::io::Result<PathBuf> {
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => private_dir(&std::env::temp_dir().join(format!("img-{}", uid())))?,
+        };
+        Ok(dir.join(format!("img-{}.sock", std::process::id())))
+    }
+
+    /// Start listening on `path`. Every received command wakes up `ctx` so the
+    /// viewer picks it up on the next frame.
+    pub fn bind(path: PathBuf, ctx: eframe::egui::Context) -> std::io::Result<Self> {
+        // A stale socket from a crashed process with a recycled pid. Anything
+        // else is left alone, and bind fails on it.
+        if let Ok(meta) = std::fs::symlink_metadata(&path)
+            && meta.file_type().is_socket()
+            && meta.uid() == uid()
+        {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path)?;
+
+        let (tx, rx) = mpsc::channel();
+        let clients: Arc<Mutex<Vec<Client>>> = Arc::new(Mutex::new(Vec::new()));
+        let clients_clone = clients.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().filter_map(|s| s.ok()) {
+                let writer = match stream.try_clone() {
+                    Ok(writer) => Arc::new(Mutex::new(writer)),
+                    Err(_) => continue,
+                };
+                clients_clone.lock().unwrap().push(writer.clone());
+
+                let tx = tx.clone();
+                let ctx = ctx.clone();
+                std::thread::spawn(move || Self::serve_client(stream, writer, tx, ctx));
+            }
+        });
+
+        Ok(Self {
+            path,
+            requests: rx,
+            clients,
+        })
+    }
+
+    fn serve_client(stream: UnixStream, writer: Client, tx: Sender<Request>, ctx: eframe::egui::Context) {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let reply = match serde_json::from_str::<Command>(&line) {
+                Ok(command) => {
+                    let (reply_tx, reply_rx) = mpsc::channel();
+                    if tx.send(Request { command, reply: reply_tx }).is_err() {
+                        break; // Viewer has shut down
+                    }
+                    ctx.request_repaint();
+                    match reply_rx.recv() {
+                        Ok(value) => value,
+                        Err(_) => break,
+                    }
+                }
+                Err(e) => json!({ "ok": false, "error": e.to_string() }),
+            };
+
+            if Self::send_line(&writer, &reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn send_line(client: &Client, value: &Value) -> std::io::Result<()> {
+        let mut stream = client.lock().unwrap();
+        writeln!(stream, "{}", value)
+    }
+
+    /// Next pending command, if any. Never blocks.
+    pub fn try_recv(&self) -> Option<Request> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Send an unsolicited event line to every connected client.
+    pub fn broadcast(&self, event: &Value) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| Self::send_line(client, event).is_ok());
+    }
+}
+
+fn uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { libc::geteuid() }
+}
+
+/// Create `dir` readable only by this user, or check that an existing one
+/// is. A shared temporary directory lets anyone create it first.
+fn private_dir(dir: &Path) -> std::io::Result<PathBuf> {
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    // Not following symlinks, which could point anywhere
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != uid() || meta.mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory", dir.display()),
+        ));
+    }
+    Ok(dir.to_path_buf())
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let cmd: Command = serde_json::from_str(r#"{"cmd": "goto", "index": 4}"#).unwrap();
+        assert_eq!(cmd, Command::Goto { index: 4 });
+
+        let cmd: Command = serde_json::from_str(r#"{"cmd": "get-current"}"#).unwrap();
+        assert_eq!(cmd, Command::GetCurrent);
+
+        let cmd: Command = serde_json::from_str(r#"{"cmd": "zoom"}"#).unwrap();
+        assert_eq!(cmd, Command::Zoom { factor: None });
+
+        assert!(serde_json::from_str::<Command>(r#"{"cmd": "explode"}"#).is_err());
+    }
+
+    #[test]
+    fn test_request_roundtrip_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("img.sock");
+        let server = IpcServer::bind(path.clone(), eframe::egui::Context::default()).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, r#"{{"cmd": "next"}}"#).unwrap();
+
+        let request = loop {
+            if let Some(request) = server.try_recv() {
+                break request;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(request.command, Command::Next);
+        request.respond(json!({ "ok": true }));
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), r#"{"ok":true}"#);
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_only_private_dirs_and_own_sockets_are_used() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+
+        let private = dir.path().join("private");
+        private_dir(&private).unwrap();
+        assert_eq!(std::fs::metadata(&private).unwrap().mode() & 0o777, 0o700);
+        private_dir(&private).unwrap();
+
+        let shared = dir.path().join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&shared).is_err());
+
+        // A file that isn't a socket is never removed to make room
+        let file = dir.path().join("file.sock");
+        std::fs::write(&file, "data").unwrap();
+        assert!(IpcServer::bind(file.clone(), eframe::egui::Context::default()).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "data");
+    }
+}
@@ -0,0 +1,132 @@
+use eframe::egui;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+
+/// `$XDG_CONFIG_HOME/img/exec/key-handler`.
+pub fn default_script() -> Option<PathBuf> {
+    Some(crate::config::config_dir()?.join("exec").join("key-handler"))
+}
+
+/// Name of a key press as passed to the script, e.g. `a`, `A`, `C-a`, `M-F5`.
+pub fn key_name(key: egui::Key, modifiers: egui::Modifiers) -> String {
+    let name = key.name();
+    let is_letter = name.len() == 1 && name.chars().all(|c| c.is_ascii_alphabetic());
+
+    let mut out = String::new();
+    if modifiers.ctrl || modifiers.mac_cmd {
+        out.push_str("C-");
+    }
+    if modifiers.alt {
+        out.push_str("M-");
+    }
+    if is_letter {
+        if modifiers.shift {
+            out.push_str(&name.to_ascii_uppercase());
+        } else {
+            out.push_str(&name.to_ascii_lowercase());
+        }
+    } else {
+        if modifiers.shift {
+            out.push_str("S-");
+        }
+        out.push_str(name);
+    }
+    out
+}
+
+/// A key-handler invocation running in the background.
+pub struct Job {
+    pub key: String,
+    pub paths: Vec<PathBuf>,
+    done: Receiver<Result<Vec<PathBuf>, String>>,
+}
+
+impl Job {
+    /// Run `script <key>` with `paths` on stdin, one per line. The script
+    /// may print paths on stdout, one per line, for files it moved or
+    /// created. `ctx` is repainted when the script exits.
+    pub fn spawn(script: PathBuf, key: String, paths: Vec<PathBuf>, ctx: egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let key_clone = key.clone();
+        let paths_clone = paths.clone();
+
+        std::thread::spawn(move || {
+            let result = Self::run(&script, &key_clone, &paths_clone);
+            let _ = tx.send(result);
+            ctx.request_repaint();
+        });
+
+        Self { key, paths, done: rx }
+    }
+
+    fn run(script: &PathBuf, key: &str, paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+        let mut child = Command::new(script)
+            .arg(key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run {}: {}", script.display(), e))?;
+
+        // Written from another thread so a script that prints before
+        // reading all of its input can't block on a full stdout pipe
+        let stdin = child.stdin.take();
+        let input: Vec<u8> = paths.iter().flat_map(|p| p.as_os_str().as_encoded_bytes().iter().copied().chain([b'\n'])).collect();
+        let writer = std::thread::spawn(move || {
+            // The script may exit without reading its input
+            let _ = stdin.map(|mut stdin| stdin.write_all(&input));
+        });
+
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        let _ = writer.join();
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).lines().filter(|l| !l.is_empty()).map(PathBuf::from).collect())
+        } else {
+            Err(format!("{} {} exited with {}", script.display(), key, output.status))
+        }
+    }
+
+    /// The script's result once it has exited.
+    pub fn try_finish(&self) -> Option<Result<Vec<PathBuf>, String>> {
+        match self.done.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err("key handler thread panicked".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_name() {
+        assert_eq!(key_name(egui::Key::A, egui::Modifiers::NONE), "a");
+        assert_eq!(key_name(egui::Key::A, egui::Modifiers::SHIFT), "A");
+        assert_eq!(key_name(egui::Key::U, egui::Modifiers::CTRL), "C-u");
+        assert_eq!(key_name(egui::Key::F5, egui::Modifiers::ALT | egui::Modifiers::SHIFT), "M-S-F5");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_job_reports_printed_paths() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("key-handler");
+        std::fs::write(&script, "#!/bin/sh\nwhile read -r p; do mv \"$p\" \"$p.$1\"; echo \"$p.$1\"; done\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let image = dir.path().join("a.png");
+        std::fs::write(&image, b"").unwrap();
+
+        let job = Job::spawn(script, "r".to_string(), vec![image.clone()], egui::Context::default());
+        let result = loop {
+            if let Some(result) = job.try_finish() {
+                break result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(result, Ok(vec![dir.path().join("a.png.r")]));
+    }
+}
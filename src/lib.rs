@@ -0,0 +1,2514 @@
+//! The `img` image viewer as a library. The reusable parts are the
+//! [`scanner`] that finds images, the [`loader`] that decodes them in the
+//! background into a size-bounded [`cache`], and the [`widget`] that shows
+//! a cached image in any `egui::Ui`. The viewer itself is started with
+//! [`run_window`] or [`terminal::run`].
+
+use cache::{CachedImage, SharedCache};
+use eframe::egui;
+use image::{DynamicImage, GenericImageView};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use widget::ZoomMode;
+
+mod adjust;
+pub mod bookmarks;
+pub mod cache;
+pub mod cli;
+mod compare;
+pub mod config;
+pub mod convert;
+mod duplicates;
+mod edit;
+mod errorlog;
+pub mod export;
+mod histogram;
+mod hud;
+pub mod info;
+mod inspector;
+mod ipc;
+pub mod keyhandler;
+pub mod loader;
+mod metadata;
+pub mod montage;
+pub mod pathlist;
+mod rename;
+pub mod scaling;
+pub mod scanner;
+mod search;
+pub mod session;
+pub mod terminal;
+pub mod transparency;
+mod trash;
+pub mod widget;
+
+/// Settings from the command line and config file.
+#[derive(Default)]
+pub struct ViewerOptions {
+    /// Print marked paths on quit, terminated by this byte
+    pub output_separator: Option<u8>,
+    /// External key-handler script, run by the Ctrl+X prefix
+    pub key_handler: Option<PathBuf>,
+    /// Copy/move targets for the number keys
+    pub bookmarks: BTreeMap<u8, PathBuf>,
+    pub collision: bookmarks::Collision,
+    pub background: transparency::BackgroundConfig,
+    /// Step over images that fail to decode when moving with J/K
+    pub skip_broken: bool,
+    /// Directory whose state is restored on start and saved on exit
+    pub session_dir: Option<PathBuf>,
+    /// Apply the saved state of `session_dir` rather than only saving it
+    pub restore_session: bool,
+}
+
+/// What the derived texture of the current image was made from.
+#[derive(Clone, PartialEq)]
+struct DerivedKey {
+    path: PathBuf,
+    rotation: u32,
+    channel: histogram::Channel,
+    adjustments: adjust::Adjustments,
+    premultiplied: bool,
+    options: egui::TextureOptions,
+}
+
+/// Apply `key`'s adjustments, channel and rotation to `image` on a
+/// background thread, repainting `ctx` when the result is ready.
+fn spawn_derived(ctx: &egui::Context, image: DynamicImage, key: &DerivedKey) -> std::sync::mpsc::Receiver<egui::ColorImage> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (ctx, adjustments, channel, rotation, premultiplied) = (ctx.clone(), key.adjustments, key.channel, key.rotation, key.premultiplied);
+    std::thread::spawn(move || {
+        let derived = histogram::extract_channel(&adjustments.apply(&image), channel);
+        let derived = rotate_image(&derived, rotation).to_rgba8();
+        let size = [derived.width() as usize, derived.height() as usize];
+        let _ = tx.send(if premultiplied {
+            egui::ColorImage::from_rgba_premultiplied(size, &derived)
+        } else {
+            egui::ColorImage::from_rgba_unmultiplied(size, &derived)
+        });
+        ctx.request_repaint();
+    });
+    rx
+}
+
+/// An undoable bookmark copy/move, with the list positions of moved images.
+struct UndoEntry {
+    operation: bookmarks::Operation,
+    removed: Vec<(usize, PathBuf)>,
+}
+
+/// The rename window: the current image (F2) or the marked ones (Shift+F2).
+struct RenameDialog {
+    paths: Vec<PathBuf>,
+    // Dimensions and EXIF dates, read on a background thread when the
+    // dialog opens
+    info: Option<Vec<rename::FileInfo>>,
+    info_rx: std::sync::mpsc::Receiver<Vec<rename::FileInfo>>,
+    template: String,
+    start: usize,
+    preview: Vec<rename::Rename>,
+    // Template parse error, if any
+    error: Option<String>,
+    // Template, start and whether the metadata was in when the preview was computed
+    preview_for: Option<(String, usize, bool)>,
+}
+
+impl RenameDialog {
+    fn new(paths: Vec<PathBuf>, template: String, ctx: &egui::Context) -> Self {
+        let (tx, info_rx) = std::sync::mpsc::channel();
+        let (to_read, ctx) = (paths.clone(), ctx.clone());
+        std::thread::spawn(move || {
+            let _ = tx.send(to_read.iter().map(|p| rename::FileInfo::read(p)).collect());
+            ctx.request_repaint();
+        });
+        Self {
+            info: None,
+            info_rx,
+            paths,
+            template,
+            start: 1,
+            preview: Vec::new(),
+            error: None,
+            preview_for: None,
+        }
+    }
+
+    /// Recompute the preview when the template, counter start or metadata
+    /// changed.
+    fn refresh_preview(&mut self) {
+        if self.info.is_none() {
+            self.info = self.info_rx.try_recv().ok();
+        }
+        let key = (self.template.clone(), self.start, self.info.is_some());
+        if self.preview_for.as_ref() == Some(&key) {
+            return;
+        }
+        match rename::Template::parse(&self.template) {
+            Ok(template) => {
+                self.preview = match &self.info {
+                    Some(info) => rename::plan(&self.paths, info, &template, self.start),
+                    None => Vec::new(),
+                };
+                self.error = None;
+            }
+            Err(e) => {
+                self.preview.clear();
+                self.error = Some(e);
+            }
+        }
+        self.preview_for = Some(key);
+    }
+
+    fn can_apply(&self) -> bool {
+        self.error.is_none() && !self.preview.is_empty() && self.preview.iter().all(|r| r.problem.is_none())
+    }
+}
+
+struct ImageViewer {
+    images: Vec<PathBuf>,
+    current_index: usize,
+    /// The image on screen, copied out of the cache so painting never
+    /// waits on the decode workers
+    current_image: Option<CachedImage>,
+    /// Texture uploaded while painting, with its path and rotation; copied
+    /// to the cache entry once the frame is drawn
+    uploaded_texture: Option<(PathBuf, u32, (egui::TextureHandle, egui::TextureOptions))>,
+    loading_image: Option<loader::LoadHandle>,
+    image_cache: SharedCache,
+    loader: loader::Loader,
+    preload_handles: HashMap<PathBuf, loader::LoadHandle>,
+    // Delete confirmation state
+    delete_pending: bool,
+    delete_timestamp: Option<std::time::Instant>,
+    show_delete_confirm: bool,
+    image_to_delete: Option<PathBuf>,
+    zoom: ZoomMode,
+    // Remote control socket and the last path announced to its clients
+    ipc: Option<ipc::IpcServer>,
+    announced_path: Option<PathBuf>,
+    // Paths still arriving on stdin
+    incoming: Option<pathlist::PathStream>,
+    // Marked images and whether navigation is restricted to them
+    marked: HashSet<PathBuf>,
+    marked_only: bool,
+    options: ViewerOptions,
+    key_handler_prefix: bool,
+    key_handler_job: Option<keyhandler::Job>,
+    undo_stack: Vec<UndoEntry>,
+    // Transient message shown at the bottom of the window
+    status: Option<(String, std::time::Instant)>,
+    rename_dialog: Option<RenameDialog>,
+    // Renamed paths whose decode was still running under the old name
+    pending_rekeys: Vec<(PathBuf, PathBuf)>,
+    // Duplicate finder, shown instead of the image while open
+    duplicates: Option<duplicates::DuplicateFinder>,
+    // Compare mode with a pinned image A, shown instead of the image
+    compare: Option<compare::Compare>,
+    inspector: Option<inspector::Inspector>,
+    histogram: Option<histogram::HistogramOverlay>,
+    channel: histogram::Channel,
+    /// Texture for the channel view and adjustments, with what it was made from
+    derived_texture: Option<(DerivedKey, egui::TextureHandle)>,
+    /// The derived image being computed; one at a time, so dragging a
+    /// slider only queues the latest value
+    derived_job: Option<(DerivedKey, std::sync::mpsc::Receiver<egui::ColorImage>)>,
+    background: transparency::Background,
+    stray_colors: Option<transparency::StrayColorOverlay>,
+    filter: scaling::FilterMode,
+    integer_scaling: bool,
+    pixel_grid: bool,
+    editor: Option<edit::Editor>,
+    /// Adjustments for all images, or per image when `adjust_per_image` is set
+    adjustments: adjust::Adjustments,
+    image_adjustments: HashMap<PathBuf, adjust::Adjustments>,
+    adjust_per_image: bool,
+    show_adjustments: bool,
+    show_background_settings: bool,
+    // Contact sheet being rendered in the background
+    montage_job: Option<std::sync::mpsc::Receiver<(Vec<PathBuf>, Vec<String>)>>,
+    /// Decode failures, shown in place of the image
+    load_errors: HashMap<PathBuf, loader::LoadError>,
+    error_log: errorlog::ErrorLog,
+    /// Direction of the last J/K step, to keep going past broken files
+    skip_forward: Option<bool>,
+    hud: hud::Hud,
+    /// Position of each path as it came in, to undo sorting
+    given_order: HashMap<PathBuf, usize>,
+    sort: session::SortOrder,
+    /// Clockwise degrees by path, kept past cache eviction and saved with
+    /// the session
+    rotations: HashMap<PathBuf, u32>,
+    sessions: Option<session::SessionStore>,
+    /// The recent folders window, when open
+    recent: Option<Vec<PathBuf>>,
+    /// The `/` or `:` prompt, when open
+    prompt: Option<search::Prompt>,
+    /// Query of the last search, for n and N
+    last_search: Option<String>,
+}
+
+impl ImageViewer {
+    fn new(
+        images: Vec<PathBuf>,
+        incoming: Option<pathlist::PathStream>,
+        options: ViewerOptions,
+        ctx: &egui::Context,
+    ) -> Self {
+        if let Some(stream) = &incoming {
+            stream.attach(ctx);
+        }
+
+        let ipc = match ipc::IpcServer::default_path().and_then(|path| ipc::IpcServer::bind(path, ctx.clone())) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                tracing::warn!("Failed to open control socket: {}", e);
+                None
+            }
+        };
+
+        let image_cache = cache::ImageCache::shared(cache::DEFAULT_CACHE_BYTES);
+        let loader = loader::Loader::new(image_cache.clone());
+        loader.attach(ctx);
+        let mut viewer = Self {
+            images,
+            current_index: 0,
+            current_image: None,
+            uploaded_texture: None,
+            loading_image: None,
+            loader,
+            image_cache,
+            preload_handles: HashMap::new(),
+            // Initialize delete state
+            delete_pending: false,
+            delete_timestamp: None,
+            show_delete_confirm: false,
+            image_to_delete: None,
+            zoom: ZoomMode::Fit,
+            ipc,
+            announced_path: None,
+            incoming,
+            marked: HashSet::new(),
+            marked_only: false,
+            options,
+            key_handler_prefix: false,
+            key_handler_job: None,
+            undo_stack: Vec::new(),
+            status: None,
+            rename_dialog: None,
+            pending_rekeys: Vec::new(),
+            duplicates: None,
+            compare: None,
+            inspector: None,
+            histogram: None,
+            channel: histogram::Channel::All,
+            derived_texture: None,
+            derived_job: None,
+            background: transparency::Background::default(),
+            stray_colors: None,
+            filter: scaling::FilterMode::default(),
+            integer_scaling: false,
+            pixel_grid: false,
+            editor: None,
+            adjustments: adjust::Adjustments::default(),
+            image_adjustments: HashMap::new(),
+            adjust_per_image: false,
+            show_adjustments: false,
+            show_background_settings: false,
+            montage_job: None,
+            load_errors: HashMap::new(),
+            error_log: errorlog::ErrorLog::default(),
+            skip_forward: None,
+            hud: hud::Hud::default(),
+            given_order: HashMap::new(),
+            sort: session::SortOrder::default(),
+            rotations: HashMap::new(),
+            sessions: session::SessionStore::open(),
+            recent: None,
+            prompt: None,
+            last_search: None,
+        };
+
+        viewer.record_given_order();
+        viewer.restore_session();
+        if !viewer.images.is_empty() {
+            viewer.load_current_image();
+            viewer.preload_adjacent_images();
+        }
+        viewer
+    }
+
+    /// Number paths that are new to the list in the order they appear.
+    fn record_given_order(&mut self) {
+        for path in &self.images {
+            let next = self.given_order.len();
+            self.given_order.entry(path.clone()).or_insert(next);
+        }
+    }
+
+    /// Re-sort the list, staying on the same image.
+    fn sort_images(&mut self) {
+        let current = self.images.get(self.current_index).cloned();
+        self.sort.sort(&mut self.images, &self.given_order);
+        if let Some(index) = current.and_then(|current| self.images.iter().position(|p| *p == current)) {
+            self.current_index = index;
+        }
+    }
+
+    fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.sort_images();
+        self.preload_adjacent_images();
+        self.set_status(format!("Sorted by {}", self.sort.name()));
+    }
+
+    /// Set up a freshly opened list of `session_dir`: note the folder as
+    /// recent and apply its saved state, or start at the first image in the
+    /// current sort order.
+    fn restore_session(&mut self) {
+        self.current_index = 0;
+        let (Some(store), Some(dir)) = (&self.sessions, self.options.session_dir.clone()) else {
+            self.sort_images();
+            return;
+        };
+        let recorded = store.add_recent(&dir);
+        let saved = store.load(&dir).filter(|_| self.options.restore_session);
+        if let Err(e) = recorded {
+            self.report_error(format!("Failed to record recent folder {}: {}", dir.display(), e));
+        }
+        let Some(saved) = saved else {
+            self.sort_images();
+            return;
+        };
+
+        self.rotations.extend(saved.rotations.into_iter().map(|(path, rotation)| (dir.join(path), rotation)));
+        let listed: HashSet<&PathBuf> = self.images.iter().collect();
+        let marked: Vec<PathBuf> = saved.marked.into_iter().map(|path| dir.join(path)).filter(|p| listed.contains(p)).collect();
+        self.marked.extend(marked);
+        self.zoom = saved.zoom.map_or(ZoomMode::Fit, ZoomMode::Scale);
+        self.sort = saved.sort;
+        self.sort.sort(&mut self.images, &self.given_order);
+        let current = saved.current.map(|path| dir.join(path));
+        self.current_index = self.images.iter().position(|p| Some(p) == current.as_ref()).unwrap_or(0);
+    }
+
+    /// The state of `dir` worth keeping, with paths relative to it.
+    fn session_snapshot(&self, dir: &Path) -> session::Session {
+        let relative = |path: &PathBuf| path.strip_prefix(dir).ok().map(Path::to_path_buf);
+        session::Session {
+            current: self.images.get(self.current_index).and_then(relative),
+            rotations: self
+                .rotations
+                .iter()
+                .filter(|(_, rotation)| **rotation != 0)
+                .filter_map(|(path, rotation)| Some((relative(path)?, *rotation)))
+                .collect(),
+            zoom: match self.zoom {
+                ZoomMode::Fit => None,
+                ZoomMode::Scale(factor) => Some(factor),
+            },
+            sort: self.sort,
+            marked: self.marked.iter().filter_map(relative).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn save_session(&mut self) {
+        let (Some(store), Some(dir)) = (&self.sessions, &self.options.session_dir) else {
+            return;
+        };
+        if let Err(e) = store.save(dir, &self.session_snapshot(dir)) {
+            self.report_error(format!("Failed to save session for {}: {}", dir.display(), e));
+        }
+    }
+
+    /// Append paths that have arrived on stdin since the last frame.
+    fn receive_incoming_paths(&mut self) {
+        let Some(stream) = &self.incoming else { return };
+        let (paths, open) = stream.drain();
+        if !open {
+            self.incoming = None;
+        }
+        self.append_images(paths);
+    }
+
+    /// Add files and directories to the end of the list, or in sort order.
+    fn append_images(&mut self, paths: Vec<PathBuf>) {
+        if paths.is_empty() {
+            return;
+        }
+
+        let was_empty = self.images.is_empty();
+        self.images.extend(scanner::expand_paths(paths));
+        self.record_given_order();
+        if self.sort != session::SortOrder::Given {
+            self.sort_images();
+        }
+        if was_empty && !self.images.is_empty() {
+            self.go_to_index(0);
+        } else {
+            self.preload_adjacent_images();
+        }
+    }
+
+    fn load_current_image(&mut self) {
+        if let Some(path) = self.images.get(self.current_index) {
+            let cache = self.image_cache.clone();
+            let path_clone = path.clone();
+
+            // Check if image is cached first
+            let is_cached = {
+                let cache = cache.lock().unwrap();
+                cache.contains(&path_clone)
+            };
+
+            if is_cached {
+                tracing::debug!(path = %path_clone.display(), "cache hit");
+                self.hud.cache_hits += 1;
+                let image = cache.lock().unwrap().get(&path_clone).cloned();
+                self.show_image(image);
+                self.loading_image = None; // Clear any pending load
+            } else if self.load_errors.contains_key(&path_clone) {
+                // Shown as an error card until the file changes
+                self.loading_image = None;
+            } else {
+                // Start async loading if not cached, taking over a preload
+                // of the same image if there is one
+                if self.loading_image.is_none() || self.loading_image.as_ref().unwrap().is_finished() {
+                    let preloading = self.preload_handles.contains_key(&path_clone);
+                    tracing::debug!(path = %path_clone.display(), preloading, "cache miss");
+                    self.hud.cache_misses += 1;
+                    let handle = match self.preload_handles.remove(&path_clone) {
+                        Some(handle) => {
+                            handle.set_priority(0);
+                            handle
+                        }
+                        None => self.loader.load(path_clone, self.filter.resize_filter(), 0),
+                    };
+                    self.loading_image = Some(handle);
+                }
+            }
+        }
+    }
+
+    fn check_loading_complete(&mut self) {
+        // The loader repaints when a decode finishes, so this never waits
+        if let Some(handle) = &mut self.loading_image
+            && let Some(result) = handle.try_take()
+        {
+            let path = handle.path().to_path_buf();
+            self.loading_image = None;
+            match result {
+                Ok(image) => self.show_image(Some(image)),
+                Err(e) => {
+                    let skip = self.skip_forward.filter(|_| self.options.skip_broken);
+                    self.record_load_error(path, e);
+                    // Keep going in the same direction until something decodes
+                    if let Some(forward) = skip {
+                        self.step(forward);
+                    }
+                }
+            }
+        }
+
+        let mut failed = Vec::new();
+        self.preload_handles.retain(|path, handle| match handle.try_take() {
+            None => true,
+            Some(Ok(_)) => false,
+            Some(Err(e)) => {
+                failed.push((path.clone(), e));
+                false
+            }
+        });
+        for (path, e) in failed {
+            self.record_load_error(path, e);
+        }
+    }
+
+    /// Make `image` the one on screen, turned the way it was left.
+    fn show_image(&mut self, image: Option<CachedImage>) {
+        self.current_image = image;
+        if let (Some(path), Some(shown)) = (self.images.get(self.current_index), &mut self.current_image)
+            && let Some(&rotation) = self.rotations.get(path)
+            && rotation != shown.rotation
+        {
+            shown.rotation = rotation;
+            shown.texture = None;
+        }
+    }
+
+    fn record_load_error(&mut self, path: PathBuf, error: loader::LoadError) {
+        if error.kind == loader::ErrorKind::Cancelled || self.load_errors.contains_key(&path) {
+            return;
+        }
+        self.report_error(format!("{}: {}", path.display(), error));
+        self.load_errors.insert(path, error);
+    }
+
+    fn hud_info(&self) -> hud::HudInfo {
+        let cache = self.image_cache.lock().unwrap();
+        hud::HudInfo {
+            loader: self.loader.stats(),
+            cache_len: cache.len(),
+            cache_bytes: cache.bytes(),
+            cache_max_bytes: cache.max_bytes(),
+            preloads: self.preload_handles.len(),
+        }
+    }
+
+    /// Keep `message` in the error log, which also prints it.
+    fn report_error(&mut self, message: String) {
+        self.error_log.push(message);
+    }
+
+    fn preload_adjacent_images(&mut self) {
+        if self.images.is_empty() {
+            return;
+        }
+
+        // Next 5 and previous 3 images, interleaved by distance so the
+        // current image (priority 0) always comes first
+        let len = self.images.len();
+        let mut wanted: HashMap<&PathBuf, u32> = HashMap::new();
+        let forward = (1..=5).map(|i| ((self.current_index + i) % len, 2 * i as u32 - 1));
+        let backward = (1..=3).map(|i| ((self.current_index + len - i % len) % len, 2 * i as u32));
+        for (idx, priority) in forward.chain(backward) {
+            if idx != self.current_index {
+                let entry = wanted.entry(&self.images[idx]).or_insert(priority);
+                *entry = (*entry).min(priority);
+            }
+        }
+
+        for (path, priority) in &wanted {
+            if let Some(handle) = self.preload_handles.get(*path) {
+                handle.set_priority(*priority);
+            } else if !self.load_errors.contains_key(*path) && !self.image_cache.lock().unwrap().contains(path) {
+                let handle = self.loader.load((*path).clone(), self.filter.resize_filter(), *priority);
+                self.preload_handles.insert((*path).clone(), handle);
+            }
+        }
+
+        // Drop finished handles and cancel decodes we've moved away from
+        self.preload_handles.retain(|path, handle| {
+            let keep = !handle.is_finished() && wanted.contains_key(path);
+            if !keep {
+                handle.cancel();
+            }
+            keep
+        });
+    }
+
+
+
+    fn next_image(&mut self) {
+        self.step(true);
+    }
+
+    fn prev_image(&mut self) {
+        self.step(false);
+    }
+
+    fn step(&mut self, forward: bool) {
+        if let Some(index) = self.neighbor_index(forward) {
+            self.go_to_index(index);
+            self.skip_forward = Some(forward);
+        }
+    }
+
+    /// The next (or previous) index, wrapping around and skipping unmarked
+    /// images when only marked ones are shown, and known broken ones when
+    /// they're skipped.
+    fn neighbor_index(&self, forward: bool) -> Option<usize> {
+        let len = self.images.len();
+        (1..=len)
+            .map(|step| {
+                if forward {
+                    (self.current_index + step) % len
+                } else {
+                    (self.current_index + len - step) % len
+                }
+            })
+            .find(|&i| {
+                let path = &self.images[i];
+                (!self.marked_only || self.marked.contains(path))
+                    && !(self.options.skip_broken && self.load_errors.contains_key(path))
+            })
+    }
+
+    /// Go to the next (or previous) image matching the last search.
+    fn jump_to_match(&mut self, forward: bool) {
+        let Some(query) = &self.last_search else {
+            self.set_status("No search yet, / starts one".to_string());
+            return;
+        };
+        let found = search::matching_indices(query, &self.images);
+        let current = self.current_index;
+        let next = if forward {
+            found.iter().find(|&&i| i > current).or(found.first())
+        } else {
+            found.iter().rev().find(|&&i| i < current).or(found.last())
+        };
+        let Some(&index) = next else {
+            self.set_status(format!("No match for {}", query));
+            return;
+        };
+        let position = found.iter().position(|&i| i == index).unwrap_or(0);
+        self.go_to_index(index);
+        self.set_status(format!("Match {} of {}", position + 1, found.len()));
+    }
+
+    /// Feed the key presses of this frame to the open prompt. Searching
+    /// goes to the best match as the query changes; Enter keeps it, or
+    /// jumps to the typed number, and Esc goes back to where it started.
+    fn handle_prompt_keys(&mut self, ctx: &egui::Context) {
+        let Some(mut prompt) = self.prompt.take() else { return };
+        let (text, backspace, enter, escape) = ctx.input_mut(|i| {
+            let text: String = i
+                .events
+                .iter()
+                .filter_map(|e| match e {
+                    egui::Event::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            (
+                text,
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Backspace),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+
+        let typed = prompt.input.len();
+        prompt.type_text(&text);
+        // Backspace on an empty prompt closes it, as in vim
+        let cancelled = escape || (backspace && prompt.input.is_empty() && typed == 0);
+        if backspace {
+            prompt.input.pop();
+        }
+        if cancelled {
+            if prompt.kind == search::PromptKind::Search && self.current_index != prompt.origin {
+                self.go_to_index(prompt.origin);
+            }
+            return;
+        }
+        if prompt.input.len() != typed || backspace {
+            prompt.refresh(&self.images);
+            if prompt.kind == search::PromptKind::Search
+                && let Some(index) = prompt.target()
+                && index != self.current_index
+            {
+                self.go_to_index(index);
+            }
+        }
+        if !enter {
+            self.prompt = Some(prompt);
+            return;
+        }
+
+        match prompt.kind {
+            _ if prompt.input.is_empty() => {}
+            search::PromptKind::Search if prompt.matches.is_empty() => {
+                self.set_status(format!("No match for {}", prompt.input));
+                self.go_to_index(prompt.origin);
+            }
+            search::PromptKind::Search => {
+                self.set_status(format!("{} found, n and N step through them", prompt.matches.len()));
+                self.last_search = Some(prompt.input);
+            }
+            search::PromptKind::Jump => match prompt.target() {
+                Some(index) if index < self.images.len() => self.go_to_index(index),
+                _ => self.set_status(format!("No image {} of {}", prompt.input, self.images.len())),
+            },
+        }
+    }
+
+    fn go_to_index(&mut self, index: usize) {
+        if index < self.images.len() {
+            // Demote the pending load to a preload, which is cancelled if
+            // the new position doesn't need it
+            if let Some(handle) = self.loading_image.take() {
+                self.preload_handles.insert(handle.path().to_path_buf(), handle);
+            }
+            self.current_image = None;
+            self.skip_forward = None;
+
+            self.current_index = index;
+            self.load_current_image();
+            self.preload_adjacent_images();
+        }
+    }
+
+    /// Show `path`, adding it to the list if needed. A directory replaces the
+    /// list with its contents.
+    fn open_path(&mut self, path: PathBuf) -> Result<(), String> {
+        if path.is_dir() {
+            let images = scanner::Scanner::default().scan(&path);
+            if images.is_empty() {
+                return Err(format!("no images in {}", path.display()));
+            }
+            self.save_session();
+            // Nothing of the old folder carries over, whether or not this one has a session
+            self.marked.clear();
+            self.marked_only = false;
+            self.zoom = ZoomMode::Fit;
+            self.sort = session::SortOrder::default();
+            self.images = images;
+            self.given_order.clear();
+            self.record_given_order();
+            self.options.session_dir = Some(path);
+            self.restore_session();
+            self.go_to_index(self.current_index);
+        } else if let Some(pos) = self.images.iter().position(|p| *p == path) {
+            self.go_to_index(pos);
+        } else if path.is_file() {
+            self.images.push(path);
+            self.record_given_order();
+            self.go_to_index(self.images.len() - 1);
+        } else {
+            return Err(format!("no such file: {}", path.display()));
+        }
+        Ok(())
+    }
+
+    fn delete_image(&mut self, path: &PathBuf) -> Result<(), std::io::Error> {
+        std::fs::remove_file(path)?;
+        // Remove from cache if present
+        {
+            let mut cache = self.image_cache.lock().unwrap();
+            cache.pop(path);
+        }
+        // Remove from preload handles if present
+        self.preload_handles.remove(path);
+        self.marked.remove(path);
+        Ok(())
+    }
+
+    fn update_image_list_after_delete(&mut self) {
+        if self.images.is_empty() {
+            return;
+        }
+
+        // Remove the deleted image from the list
+        if let Some(path) = self.image_to_delete.clone()
+            && self.remove_from_list(&path)
+        {
+            self.reload_current_image();
+        }
+
+        // Reset delete state
+        self.show_delete_confirm = false;
+        self.image_to_delete = None;
+    }
+
+    /// Drop `path` from the image list, keeping `current_index` on the same
+    /// image or, if it was the one removed, on the image after it.
+    fn remove_from_list(&mut self, path: &PathBuf) -> bool {
+        let Some(pos) = self.images.iter().position(|p| p == path) else {
+            return false;
+        };
+        self.images.remove(pos);
+        self.marked.remove(path);
+
+        // Adjust current_index if necessary
+        if pos < self.current_index {
+            self.current_index = self.current_index.saturating_sub(1);
+        } else if pos == self.current_index {
+            // If we deleted the current image, stay at the same index
+            // (which now points to the next image)
+            if self.current_index >= self.images.len() && self.current_index > 0 {
+                self.current_index = self.current_index.saturating_sub(1);
+            }
+        }
+        true
+    }
+
+    fn reload_current_image(&mut self) {
+        self.current_image = None;
+        self.loading_image = None;
+
+        // If no images left, reset state
+        if self.images.is_empty() {
+            self.current_index = 0;
+        } else {
+            self.load_current_image();
+            self.preload_adjacent_images();
+        }
+    }
+
+    /// Paths an action applies to: the marked images, or the current one.
+    fn target_paths(&self) -> Vec<PathBuf> {
+        if self.marked.is_empty() {
+            self.images.get(self.current_index).cloned().into_iter().collect()
+        } else {
+            self.marked_paths().into_iter().cloned().collect()
+        }
+    }
+
+    /// Re-stat `paths` after something outside the viewer touched them:
+    /// vanished files leave the list, the rest are decoded again.
+    fn refresh_paths(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.image_cache.lock().unwrap().pop(path);
+            self.load_errors.remove(path);
+            if let Some(handle) = self.preload_handles.remove(path) {
+                handle.cancel();
+            }
+            if !path.exists() {
+                self.remove_from_list(path);
+            }
+        }
+        self.reload_current_image();
+    }
+
+    fn run_key_handler(&mut self, key: String, ctx: &egui::Context) {
+        let Some(script) = self.options.key_handler.clone() else {
+            self.set_status("No key handler configured".to_string());
+            return;
+        };
+        if let Some(job) = &self.key_handler_job {
+            // Its paths are refreshed when it exits, which another run would lose
+            self.set_status(format!("Key handler is still running {}", job.key));
+            return;
+        }
+        let paths = self.target_paths();
+        if paths.is_empty() {
+            return;
+        }
+        self.key_handler_job = Some(keyhandler::Job::spawn(script, key, paths, ctx.clone()));
+    }
+
+    fn set_status(&mut self, message: String) {
+        self.status = Some((message, std::time::Instant::now()));
+    }
+
+    /// Switch the display filter. Display copies shrunk with another one are
+    /// decoded again, the current image first, which stays up meanwhile.
+    fn set_filter(&mut self, filter: scaling::FilterMode) {
+        let redecode = filter.resize_filter() != self.filter.resize_filter();
+        self.filter = filter;
+        if !redecode {
+            return;
+        }
+        self.image_cache.lock().unwrap().clear();
+        for (_, handle) in self.preload_handles.drain() {
+            handle.cancel();
+        }
+        if let Some(handle) = self.loading_image.take() {
+            handle.cancel();
+        }
+        self.load_current_image();
+        self.preload_adjacent_images();
+    }
+
+    /// Show only `channel` as grayscale, or all channels if it's already shown.
+    fn toggle_channel(&mut self, channel: histogram::Channel) {
+        self.channel = if self.channel == channel { histogram::Channel::All } else { channel };
+        self.set_status(format!("Showing {} channel", self.channel.label()));
+    }
+
+    /// Copy or move the current or marked images to bookmark `slot`.
+    fn transfer_to_bookmark(&mut self, slot: u8, transfer: bookmarks::Transfer) {
+        let Some(dir) = self.options.bookmarks.get(&slot).map(|d| bookmarks::expand_home(d)) else {
+            self.set_status(format!("Bookmark {} is not set", slot));
+            return;
+        };
+        let paths = self.target_paths();
+        if paths.is_empty() {
+            return;
+        }
+
+        let (operation, errors) = bookmarks::transfer(&paths, &dir, transfer, self.options.collision);
+        for e in &errors {
+            self.report_error(format!("Failed to transfer image: {}", e));
+        }
+        let verb = match transfer {
+            bookmarks::Transfer::Copy => "Copied",
+            bookmarks::Transfer::Move => "Moved",
+        };
+        let mut message = format!("{} {} to {}", verb, operation.sources().count(), dir.display());
+        if !errors.is_empty() {
+            message.push_str(&format!(" ({} failed)", errors.len()));
+        }
+        self.set_status(message);
+        if operation.is_empty() {
+            return;
+        }
+
+        let mut removed = Vec::new();
+        if transfer == bookmarks::Transfer::Move {
+            for source in operation.sources() {
+                if let Some(pos) = self.images.iter().position(|p| p == source) {
+                    removed.push((pos, source.clone()));
+                }
+            }
+            // Remove from the back so earlier positions stay valid for undo
+            for (_, path) in removed.iter().rev() {
+                self.image_cache.lock().unwrap().pop(path);
+                self.preload_handles.remove(path);
+                self.remove_from_list(path);
+            }
+            self.reload_current_image();
+        }
+        self.undo_stack.push(UndoEntry { operation, removed });
+    }
+
+    fn undo_transfer(&mut self) {
+        let Some(entry) = self.undo_stack.pop() else {
+            self.set_status("Nothing to undo".to_string());
+            return;
+        };
+        let dir = entry.operation.dir.clone();
+        match entry.operation.undo() {
+            Ok(()) => self.set_status(format!("Undid transfer to {}", dir.display())),
+            Err(e) => {
+                self.report_error(format!("Failed to undo: {}", e));
+                self.set_status(format!("Undo failed: {}", e));
+            }
+        }
+
+        // Put moved images back where they were
+        for (pos, path) in &entry.removed {
+            if path.exists() {
+                let pos = (*pos).min(self.images.len());
+                self.images.insert(pos, path.clone());
+            }
+        }
+        if let Some((pos, _)) = entry.removed.first() {
+            self.go_to_index((*pos).min(self.images.len().saturating_sub(1)));
+        }
+    }
+
+    fn open_rename_dialog(&mut self, batch: bool, ctx: &egui::Context) {
+        if batch {
+            let paths: Vec<PathBuf> = self.marked_paths().into_iter().cloned().collect();
+            if paths.is_empty() {
+                self.set_status("No marked images to rename".to_string());
+                return;
+            }
+            self.rename_dialog = Some(RenameDialog::new(paths, "{n:04}_{stem}.{ext}".to_string(), ctx));
+        } else if let Some(path) = self.images.get(self.current_index) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            self.rename_dialog = Some(RenameDialog::new(vec![path.clone()], name, ctx));
+        }
+    }
+
+    /// Point everything that refers to a renamed file at its new name, so
+    /// nothing has to be decoded again.
+    fn apply_renames(&mut self, renames: &[(PathBuf, PathBuf)]) {
+        let mut cache = self.image_cache.lock().unwrap();
+        for (from, to) in renames {
+            if let Some(entry) = self.images.iter_mut().find(|p| *p == from) {
+                *entry = to.clone();
+            }
+            if self.marked.remove(from) {
+                self.marked.insert(to.clone());
+            }
+            if let Some(cached) = cache.pop(from) {
+                cache.put(to.clone(), cached);
+            }
+            if let Some(error) = self.load_errors.remove(from) {
+                self.load_errors.insert(to.clone(), error);
+            }
+            if let Some(rotation) = self.rotations.remove(from) {
+                self.rotations.insert(to.clone(), rotation);
+            }
+            if let Some(index) = self.given_order.remove(from) {
+                self.given_order.insert(to.clone(), index);
+            }
+            if let Some(handle) = self.preload_handles.remove(from) {
+                self.preload_handles.insert(to.clone(), handle);
+                self.pending_rekeys.push((from.clone(), to.clone()));
+            } else if self.loading_image.is_some() {
+                self.pending_rekeys.push((from.clone(), to.clone()));
+            }
+        }
+    }
+
+    /// Move cache entries that in-flight decodes stored under a pre-rename path.
+    fn settle_renamed_cache_entries(&mut self) {
+        if self.pending_rekeys.is_empty() {
+            return;
+        }
+        let mut cache = self.image_cache.lock().unwrap();
+        self.pending_rekeys.retain(|(from, to)| {
+            if let Some(cached) = cache.pop(from) {
+                cache.put(to.clone(), cached);
+                return false;
+            }
+            // Keep waiting while a decode that may insert `from` is running
+            self.preload_handles.get(to).is_some_and(|h| !h.is_finished()) || self.loading_image.is_some()
+        });
+    }
+
+    fn show_rename_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.rename_dialog else { return };
+        dialog.refresh_preview();
+
+        let mut open = true;
+        let mut apply = false;
+        let mut cancel = false;
+        let title = if dialog.paths.len() == 1 {
+            "Rename".to_string()
+        } else {
+            format!("Rename {} images", dialog.paths.len())
+        };
+        egui::Window::new(title)
+            .open(&mut open)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Name:");
+                    let response = ui.add(egui::TextEdit::singleline(&mut dialog.template).desired_width(320.0));
+                    if dialog.preview_for.is_none() {
+                        response.request_focus();
+                    }
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        apply = true;
+                    }
+                });
+                if dialog.paths.len() > 1 {
+                    ui.horizontal(|ui| {
+                        ui.label("Start at:");
+                        ui.add(egui::DragValue::new(&mut dialog.start).clamp_range(0..=999_999));
+                    });
+                }
+                ui.small("Tokens: {n}, {n:04}, {stem}, {ext}, {w}, {h}, {exif.date:%Y%m%d}");
+                if let Some(error) = &dialog.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.separator();
+
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    if dialog.info.is_none() && dialog.error.is_none() {
+                        ui.label("reading metadata...");
+                    }
+                    egui::Grid::new("rename_preview").striped(true).show(ui, |ui| {
+                        for rename in &dialog.preview {
+                            ui.label(rename.from.file_name().unwrap_or_default().to_string_lossy());
+                            ui.label("→");
+                            match &rename.problem {
+                                Some(problem) => ui.colored_label(ui.visuals().error_fg_color, problem),
+                                None => ui.label(rename.to.file_name().unwrap_or_default().to_string_lossy()),
+                            };
+                            ui.end_row();
+                        }
+                    });
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(dialog.can_apply(), egui::Button::new("Rename")).clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+
+        if apply && dialog.can_apply() {
+            let (done, errors) = rename::apply(&dialog.preview);
+            for e in &errors {
+                self.report_error(format!("Failed to rename image: {}", e));
+            }
+            let mut message = format!("Renamed {} image(s)", done.len());
+            if !errors.is_empty() {
+                message.push_str(&format!(" ({} failed)", errors.len()));
+            }
+            self.rename_dialog = None;
+            self.apply_renames(&done);
+            self.set_status(message);
+        } else if cancel || !open || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.rename_dialog = None;
+        }
+    }
+
+    /// Enter compare mode with the current image pinned as A, or leave it.
+    fn toggle_compare(&mut self, ctx: &egui::Context) {
+        if self.compare.take().is_some() {
+            return;
+        }
+        if let (Some(path), Some(image)) = (self.images.get(self.current_index), &self.current_image) {
+            let pinned = rotate_image(&image.display_image, image.rotation);
+            self.compare = Some(compare::Compare::new(path.clone(), pinned, ctx));
+        }
+    }
+
+    /// Adjustments in effect for the current image.
+    fn current_adjustments(&self) -> adjust::Adjustments {
+        if !self.adjust_per_image {
+            return self.adjustments;
+        }
+        self.images
+            .get(self.current_index)
+            .and_then(|path| self.image_adjustments.get(path))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_current_adjustments(&mut self, adjustments: adjust::Adjustments) {
+        if !self.adjust_per_image {
+            self.adjustments = adjustments;
+        } else if let Some(path) = self.images.get(self.current_index) {
+            if adjustments.is_identity() {
+                self.image_adjustments.remove(path);
+            } else {
+                self.image_adjustments.insert(path.clone(), adjustments);
+            }
+        }
+    }
+
+    fn show_adjustments_window(&mut self, ctx: &egui::Context) {
+        if !self.show_adjustments {
+            return;
+        }
+        let mut adjustments = self.current_adjustments();
+        let mut per_image = self.adjust_per_image;
+        let mut open = true;
+        egui::Window::new("Adjustments")
+            .open(&mut open)
+            .resizable(false)
+            .default_pos(egui::pos2(20.0, 60.0))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut per_image, false, "All images");
+                    ui.radio_value(&mut per_image, true, "This image");
+                });
+                adjustments.sliders(ui);
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        adjustments = adjust::Adjustments::default();
+                    }
+                    ui.small("Shift+A resets, E exports a copy");
+                });
+            });
+        self.show_adjustments = open;
+
+        // Switching scope carries the current settings over
+        self.adjust_per_image = per_image;
+        self.set_current_adjustments(adjustments);
+    }
+
+    /// Shift+B window for the background behind transparent pixels.
+    fn show_background_window(&mut self, ctx: &egui::Context) {
+        if !self.show_background_settings {
+            return;
+        }
+        let config = &mut self.options.background;
+        let mut background = self.background;
+        let mut open = true;
+        egui::Window::new("Background")
+            .open(&mut open)
+            .resizable(false)
+            .default_pos(egui::pos2(20.0, 60.0))
+            .show(ctx, |ui| {
+                for mode in transparency::Background::ALL {
+                    if mode != transparency::Background::Custom || config.custom.is_some() {
+                        ui.radio_value(&mut background, mode, mode.label());
+                    }
+                }
+                ui.separator();
+                ui.add(egui::Slider::new(&mut config.checker_size, 2.0..=64.0).text("Checker size"));
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(&mut config.checker_colors[0].0);
+                    ui.color_edit_button_srgb(&mut config.checker_colors[1].0);
+                    ui.label("Checker colors");
+                });
+                ui.horizontal(|ui| {
+                    let mut enabled = config.custom.is_some();
+                    if ui.checkbox(&mut enabled, "Custom color").changed() {
+                        config.custom = enabled.then_some(transparency::Rgb([0x40, 0x40, 0x40]));
+                    }
+                    if let Some(custom) = &mut config.custom {
+                        ui.color_edit_button_srgb(&mut custom.0);
+                    }
+                });
+                ui.small("B cycles the background");
+            });
+        self.show_background_settings = open;
+
+        if config.custom.is_none() && background == transparency::Background::Custom {
+            background = transparency::Background::Checkerboard;
+        }
+        self.background = background;
+    }
+
+    /// The Ctrl+O list of recent folders; clicking one opens it.
+    fn show_recent_window(&mut self, ctx: &egui::Context) {
+        let Some(recent) = &self.recent else { return };
+        let mut open = true;
+        let mut chosen = None;
+        egui::Window::new("Recent folders")
+            .open(&mut open)
+            .default_size(egui::vec2(420.0, 300.0))
+            .show(ctx, |ui| {
+                if recent.is_empty() {
+                    ui.label("No recent folders");
+                    return;
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for dir in recent {
+                        if ui.selectable_label(false, dir.display().to_string()).clicked() {
+                            chosen = Some(dir.clone());
+                        }
+                    }
+                });
+            });
+        if !open {
+            self.recent = None;
+        }
+        if let Some(dir) = chosen {
+            self.recent = None;
+            if let Err(e) = self.open_path(dir) {
+                self.report_error(format!("Failed to open folder: {}", e));
+            }
+        }
+    }
+
+    fn open_editor(&mut self) {
+        let Some(path) = self.images.get(self.current_index) else { return };
+        let Some(cached) = self.current_image.clone() else { return };
+        self.editor = Some(edit::Editor::new(
+            path.clone(),
+            cached.display_image,
+            cached.rotation,
+            cached.original_size,
+            self.current_adjustments(),
+        ));
+    }
+
+    fn handle_edit_action(&mut self, action: edit::EditAction) {
+        match action {
+            edit::EditAction::Close => self.editor = None,
+            edit::EditAction::Saved(path) => {
+                self.set_status(format!("Saved {}", path.display()));
+                // The original was overwritten, drop the stale copy
+                if let Some(index) = self.images.iter().position(|p| *p == path) {
+                    self.image_cache.lock().unwrap().pop(&path);
+                    self.load_errors.remove(&path);
+                    if index == self.current_index {
+                        self.reload_current_image();
+                    }
+                }
+            }
+        }
+    }
+
+    fn toggle_duplicates(&mut self, ctx: &egui::Context) {
+        if self.duplicates.take().is_none() {
+            self.duplicates = Some(duplicates::DuplicateFinder::start(&self.images, ctx));
+        }
+    }
+
+    fn handle_duplicate_action(&mut self, action: duplicates::Action) {
+        match action {
+            duplicates::Action::Open(path) => {
+                self.duplicates = None;
+                if let Some(index) = self.images.iter().position(|p| *p == path) {
+                    self.go_to_index(index);
+                }
+            }
+            duplicates::Action::Trash(paths) => {
+                let trashed = self.trash_images(&paths);
+                if let Some(finder) = &mut self.duplicates {
+                    finder.remove(&trashed);
+                }
+                self.set_status(format!("Moved {} image(s) to the trash", trashed.len()));
+            }
+            duplicates::Action::Close => self.duplicates = None,
+        }
+    }
+
+    /// Move `paths` to the trash and drop them from the list. Returns the
+    /// ones that were trashed.
+    fn trash_images(&mut self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut trashed = Vec::new();
+        for path in paths {
+            if let Err(e) = trash::move_to_trash(path) {
+                self.report_error(format!("Failed to trash {}: {}", path.display(), e));
+                continue;
+            }
+            self.image_cache.lock().unwrap().pop(path);
+            self.preload_handles.remove(path);
+            self.remove_from_list(path);
+            trashed.push(path.clone());
+        }
+        if !trashed.is_empty() {
+            self.reload_current_image();
+        }
+        trashed
+    }
+
+    fn check_key_handler_finished(&mut self) {
+        let Some(result) = self.key_handler_job.as_ref().and_then(|job| job.try_finish()) else {
+            return;
+        };
+        let job = self.key_handler_job.take().unwrap();
+        let reported = match result {
+            Ok(reported) => reported,
+            Err(e) => {
+                self.report_error(format!("Key handler failed: {}", e));
+                Vec::new()
+            }
+        };
+
+        // Paths the script printed stand in, in order, for the ones that
+        // vanished, so moved or renamed files stay in the list
+        let listed: HashSet<&PathBuf> = self.images.iter().collect();
+        let mut new_paths = reported.into_iter().filter(|p| p.exists() && !listed.contains(p));
+        let renames: Vec<(PathBuf, PathBuf)> =
+            job.paths.iter().filter(|p| !p.exists()).filter_map(|from| Some((from.clone(), new_paths.next()?))).collect();
+        let extra: Vec<PathBuf> = new_paths.collect();
+        self.apply_renames(&renames);
+
+        // Refresh even on failure, the script may have done part of its work
+        let changed: Vec<PathBuf> = job
+            .paths
+            .into_iter()
+            .map(|path| renames.iter().find(|(from, _)| *from == path).map_or(path, |(_, to)| to.clone()))
+            .collect();
+        self.refresh_paths(&changed);
+        self.append_images(extra);
+    }
+
+    /// Render the marked images, or all of them, to a contact sheet next to
+    /// the first one.
+    fn export_montage(&mut self, ctx: &egui::Context) {
+        if self.montage_job.is_some() {
+            return;
+        }
+        let paths: Vec<PathBuf> = if self.marked.is_empty() {
+            self.images.clone()
+        } else {
+            self.marked_paths().into_iter().cloned().collect()
+        };
+        let Some(dir) = paths.first().and_then(|p| p.parent()) else {
+            return;
+        };
+        // Large sets are split over several sheets, none of which may exist yet
+        let layout = montage::Layout::default();
+        let sheets = layout.sheet_count(paths.len());
+        let out = (1..)
+            .map(|n| dir.join(if n == 1 { "montage.png".to_string() } else { format!("montage{}.png", n) }))
+            .find(|p| montage::sheet_paths(p, sheets).iter().all(|sheet| !sheet.exists()))
+            .unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(montage::export(&paths, &layout, &out));
+            ctx.request_repaint();
+        });
+        self.montage_job = Some(rx);
+        self.set_status("Rendering contact sheet...".to_string());
+    }
+
+    fn check_montage_finished(&mut self) {
+        let Some(Ok((written, errors))) = self.montage_job.as_ref().map(|rx| rx.try_recv()) else {
+            return;
+        };
+        self.montage_job = None;
+        for error in &errors {
+            self.report_error(format!("Montage: {}", error));
+        }
+        match written.first() {
+            Some(path) if errors.is_empty() => self.set_status(format!("Saved {}", path.display())),
+            Some(path) => self.set_status(format!("Saved {} ({} errors)", path.display(), errors.len())),
+            None => self.set_status("Failed to save contact sheet".to_string()),
+        }
+    }
+
+    /// Pick up finished loads, paths from stdin, remote commands and the
+    /// results of background jobs.
+    fn poll_background(&mut self) {
+        // Check if any async loading has completed
+        self.check_loading_complete();
+
+        self.receive_incoming_paths();
+        self.handle_ipc_commands();
+        self.check_key_handler_finished();
+        self.check_montage_finished();
+        self.settle_renamed_cache_entries();
+    }
+
+    /// Copy the texture uploaded this frame to its cache entry, outside the
+    /// paint closure so painting never waits on the cache lock.
+    fn store_uploaded_texture(&mut self) {
+        let Some((path, rotation, texture)) = self.uploaded_texture.take() else {
+            return;
+        };
+        if let Some(cached) = self.image_cache.lock().unwrap().get_mut(&path) {
+            cached.rotation = rotation;
+            cached.texture = Some(texture);
+        }
+    }
+
+    fn rotate_current_image(&mut self) {
+        let (Some(path), Some(shown)) = (self.images.get(self.current_index), &mut self.current_image) else {
+            return;
+        };
+        // Increment rotation by 90 degrees clockwise
+        shown.rotation = (shown.rotation + 90) % 360;
+        // Clear the texture so it gets recreated with the new rotation
+        shown.texture = None;
+        self.uploaded_texture = None;
+
+        // Remembered in the cache for when the image is shown again, and
+        // past eviction for the session
+        if let Some(cached) = self.image_cache.lock().unwrap().get_mut(path) {
+            cached.rotation = shown.rotation;
+            cached.texture = None;
+        }
+        self.rotations.insert(path.clone(), shown.rotation);
+    }
+
+    fn toggle_mark_current(&mut self) {
+        if let Some(path) = self.images.get(self.current_index)
+            && !self.marked.remove(path)
+        {
+            self.marked.insert(path.clone());
+        }
+    }
+
+    fn invert_marks(&mut self) {
+        self.marked = self.images.iter().filter(|p| !self.marked.contains(*p)).cloned().collect();
+    }
+
+    /// Marked paths in list order.
+    fn marked_paths(&self) -> Vec<&PathBuf> {
+        self.images.iter().filter(|p| self.marked.contains(*p)).collect()
+    }
+
+    fn write_marked(&self, out: &mut impl std::io::Write, separator: u8) -> std::io::Result<()> {
+        for path in self.marked_paths() {
+            out.write_all(path.as_os_str().as_encoded_bytes())?;
+            out.write_all(&[separator])?;
+        }
+        out.flush()
+    }
+
+    fn current_rotation(&self) -> u32 {
+        self.current_image.as_ref().map_or(0, |image| image.rotation)
+    }
+
+    fn zoom_by(&mut self, step: f32) {
+        let factor = match self.zoom {
+            ZoomMode::Fit => 1.0,
+            ZoomMode::Scale(factor) => factor,
+        };
+        let factor = if self.integer_scaling {
+            scaling::integer_step(factor, step > 1.0)
+        } else {
+            factor * step
+        };
+        self.zoom = ZoomMode::Scale(factor.clamp(0.05, 32.0));
+    }
+
+    fn current_state_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "index": self.current_index,
+            "count": self.images.len(),
+            "path": self.images.get(self.current_index),
+            "rotation": self.current_rotation(),
+            "zoom": match self.zoom {
+                ZoomMode::Fit => serde_json::Value::from("fit"),
+                ZoomMode::Scale(factor) => serde_json::Value::from(factor),
+            },
+        })
+    }
+
+    /// Run commands received on the control socket. Called from `update`, so
+    /// commands execute on the UI thread like key presses do.
+    fn handle_ipc_commands(&mut self) {
+        while let Some(request) = self.ipc.as_ref().and_then(|ipc| ipc.try_recv()) {
+            let result = match request.command.clone() {
+                ipc::Command::Open { path } => self.open_path(path),
+                ipc::Command::Next => {
+                    self.next_image();
+                    Ok(())
+                }
+                ipc::Command::Prev => {
+                    self.prev_image();
+                    Ok(())
+                }
+                ipc::Command::Goto { index } => {
+                    if index < self.images.len() {
+                        self.go_to_index(index);
+                        Ok(())
+                    } else {
+                        Err(format!("index {} out of range ({} images)", index, self.images.len()))
+                    }
+                }
+                ipc::Command::Rotate => {
+                    self.rotate_current_image();
+                    Ok(())
+                }
+                ipc::Command::Zoom { factor } => match factor {
+                    None => {
+                        self.zoom = ZoomMode::Fit;
+                        Ok(())
+                    }
+                    Some(factor) if factor > 0.0 => {
+                        self.zoom = ZoomMode::Scale(factor);
+                        Ok(())
+                    }
+                    Some(factor) => Err(format!("invalid zoom factor {}", factor)),
+                },
+                ipc::Command::GetCurrent | ipc::Command::List => Ok(()),
+            };
+
+            let reply = match (result, &request.command) {
+                (Err(e), _) => serde_json::json!({ "ok": false, "error": e }),
+                (Ok(()), ipc::Command::List) => serde_json::json!({ "ok": true, "images": self.images }),
+                (Ok(()), _) => {
+                    let mut reply = self.current_state_json();
+                    reply["ok"] = true.into();
+                    reply
+                }
+            };
+            request.respond(reply);
+        }
+    }
+
+    /// Tell socket clients when the current image has changed.
+    fn announce_current_image(&mut self) {
+        let current = self.images.get(self.current_index).cloned();
+        if current != self.announced_path {
+            if let Some(ipc) = &self.ipc {
+                let mut event = self.current_state_json();
+                event["event"] = "current-changed".into();
+                ipc.broadcast(&event);
+            }
+            self.announced_path = current;
+        }
+    }
+
+    fn handle_keys(&mut self, ctx: &egui::Context) {
+        if self.rename_dialog.is_some() {
+            return;
+        }
+        // The prompt takes all typing until Enter or Esc
+        if self.prompt.is_some() {
+            self.handle_prompt_keys(ctx);
+            return;
+        }
+        // Edit mode keeps the keyboard to itself, E or Esc leaves it
+        if self.editor.is_some() {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::E) || i.key_pressed(egui::Key::Escape)) {
+                self.editor = None;
+            }
+            return;
+        }
+        // The duplicates view hides the current image, so keys that act on
+        // it wait until it's closed
+        if self.duplicates.is_some() {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::D) || i.key_pressed(egui::Key::Escape)) {
+                self.duplicates = None;
+            }
+            return;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::E)) {
+            self.export_montage(ctx);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::L)) {
+            self.error_log.open = !self.error_log.open;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::F12)) {
+            self.hud.open = !self.hud.open;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::O)) {
+            self.recent = match self.recent {
+                Some(_) => None,
+                None => Some(self.sessions.as_ref().map(session::SessionStore::recent).unwrap_or_default()),
+            };
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::S)) {
+            self.cycle_sort();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::E)) {
+            self.open_editor();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::J)) {
+            self.next_image();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::K)) {
+            self.prev_image();
+        }
+        // Channel view: Alt+R/G/B/A show one channel as grayscale
+        for (key, channel) in [
+            (egui::Key::R, histogram::Channel::Red),
+            (egui::Key::G, histogram::Channel::Green),
+            (egui::Key::B, histogram::Channel::Blue),
+            (egui::Key::A, histogram::Channel::Alpha),
+        ] {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::ALT, key)) {
+                self.toggle_channel(channel);
+            }
+        }
+        // Search: / finds paths, n and N step through the matches, : jumps
+        // to a number and g and G to the ends
+        let typed = |text: &str| ctx.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Text(t) if t == text)));
+        if typed("/") {
+            self.prompt = Some(search::Prompt::new(search::PromptKind::Search, self.current_index));
+        } else if typed(":") {
+            self.prompt = Some(search::Prompt::new(search::PromptKind::Jump, self.current_index));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::N)) {
+            self.jump_to_match(false);
+        } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::N)) {
+            self.jump_to_match(true);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::G)) {
+            self.go_to_index(self.images.len().saturating_sub(1));
+        } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::G)) {
+            self.go_to_index(0);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::R)) {
+            self.rotate_current_image();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::Plus) || i.key_pressed(egui::Key::Equals)) {
+            self.zoom_by(1.25);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::Minus)) {
+            self.zoom_by(0.8);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::Num0)) {
+            self.zoom = ZoomMode::Fit;
+        }
+        // Bookmarks: 1-9 copy, Ctrl+1-9 move, Ctrl+Z undoes the last one
+        const BOOKMARK_KEYS: [egui::Key; 9] = [
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+            egui::Key::Num8,
+            egui::Key::Num9,
+        ];
+        for (slot, key) in (1..).zip(BOOKMARK_KEYS) {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, key)) {
+                self.transfer_to_bookmark(slot, bookmarks::Transfer::Move);
+            } else if ctx.input(|i| i.key_pressed(key)) {
+                self.transfer_to_bookmark(slot, bookmarks::Transfer::Copy);
+            }
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::Z)) {
+            self.undo_transfer();
+        }
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::D)) {
+            self.toggle_duplicates(ctx);
+        }
+
+        // Compare: C pins the current image as A, V cycles views, X flickers
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::C)) {
+            self.toggle_compare(ctx);
+        }
+        if let Some(compare) = &mut self.compare {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::V)) {
+                compare.cycle_view();
+            }
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::X)) {
+                compare.flicker();
+            }
+            if ctx.input(|i| i.key_pressed(egui::Key::Num0)) {
+                compare.reset_view();
+            }
+            if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+                self.compare = None;
+            }
+        }
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::H)) {
+            self.histogram = match self.histogram {
+                Some(_) => None,
+                None => Some(histogram::HistogramOverlay::default()),
+            };
+        }
+
+        // Transparency: B cycles the background, Shift+B shows its settings, T flags
+        // transparent pixels with color
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::B)) {
+            self.show_background_settings = !self.show_background_settings;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::B)) {
+            self.background = self.background.next(&self.options.background);
+            self.set_status(format!("Background: {}", self.background.label()));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::T)) {
+            self.stray_colors = match self.stray_colors {
+                Some(_) => None,
+                None => Some(transparency::StrayColorOverlay::default()),
+            };
+        }
+
+        // Filtering: P cycles the filter, Shift+I toggles integer scaling, Ctrl+G the pixel grid
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::P)) {
+            self.set_filter(self.filter.next());
+            self.set_status(format!("Filter: {}", self.filter.label()));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::I)) {
+            self.integer_scaling = !self.integer_scaling;
+            self.set_status(format!("Integer scaling {}", if self.integer_scaling { "on" } else { "off" }));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::G)) {
+            self.pixel_grid = !self.pixel_grid;
+        }
+
+        // Adjustments: A shows the sliders, Shift+A resets them
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::A)) {
+            self.set_current_adjustments(adjust::Adjustments::default());
+            self.set_status("Adjustments reset".to_string());
+        } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::A)) {
+            self.show_adjustments = !self.show_adjustments;
+        }
+
+        // Pixel inspector: I shows the loupe, clicking copies the hex color
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::I)) {
+            self.inspector = match self.inspector {
+                Some(_) => None,
+                None => Some(inspector::Inspector::default()),
+            };
+        }
+
+        // Rename: F2 for the current image, Shift+F2 for the marked ones
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::F2)) {
+            self.open_rename_dialog(true, ctx);
+        } else if ctx.input(|i| i.key_pressed(egui::Key::F2)) {
+            self.open_rename_dialog(false, ctx);
+        }
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::X)) {
+            self.key_handler_prefix = true;
+        }
+
+        // Marks: m toggles, Shift+M inverts, Ctrl+M clears, F shows marked only
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::M)) {
+            self.marked.clear();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::SHIFT, egui::Key::M)) {
+            self.invert_marks();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::M)) {
+            self.toggle_mark_current();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::F)) {
+            self.marked_only = !self.marked_only;
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::Q)) {
+            if let Some(separator) = self.options.output_separator
+                && let Err(e) = self.write_marked(&mut std::io::stdout().lock(), separator)
+            {
+                eprintln!("Failed to write marked images: {}", e);
+            }
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        // Handle delete confirmation (dd like vim)
+        if ctx.input(|i| i.key_pressed(egui::Key::D)) {
+            let now = std::time::Instant::now();
+
+            if self.delete_pending {
+                // Check if second 'd' was pressed within 1 second
+                if let Some(timestamp) = self.delete_timestamp
+                    && now.duration_since(timestamp).as_millis() < 1000
+                {
+                    // Valid dd sequence - show confirmation
+                    if let Some(path) = self.images.get(self.current_index) {
+                        self.show_delete_confirm = true;
+                        self.image_to_delete = Some(path.clone());
+                    }
+                }
+                // Reset state
+                self.delete_pending = false;
+                self.delete_timestamp = None;
+            } else {
+                // First 'd' press
+                self.delete_pending = true;
+                self.delete_timestamp = Some(now);
+            }
+        }
+    }
+}
+
+impl eframe::App for ImageViewer {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if let Some(cpu_usage) = frame.info().cpu_usage {
+            self.hud.record_frame(std::time::Duration::from_secs_f32(cpu_usage));
+        }
+        self.poll_background();
+
+        // After the Ctrl+X prefix the next key goes to the key handler and
+        // nowhere else
+        if self.key_handler_prefix {
+            let pressed = ctx.input_mut(|i| {
+                let pressed = i.events.iter().find_map(|e| match e {
+                    egui::Event::Key { key, pressed: true, modifiers, .. } => Some((*key, *modifiers)),
+                    _ => None,
+                });
+                if pressed.is_some() {
+                    i.events.retain(|e| !matches!(e, egui::Event::Key { .. } | egui::Event::Text(_)));
+                }
+                pressed
+            });
+            if let Some((key, modifiers)) = pressed {
+                self.key_handler_prefix = false;
+                if key != egui::Key::Escape {
+                    self.run_key_handler(keyhandler::key_name(key, modifiers), ctx);
+                }
+            }
+        }
+
+        // Before the central panel, which takes whatever space is left
+        if let Some(prompt) = &self.prompt
+            && let Some(index) = prompt.show(ctx, &self.images, self.current_index)
+        {
+            self.last_search = self.prompt.take().map(|prompt| prompt.input);
+            self.go_to_index(index);
+        }
+
+        let current_rotation = self.current_rotation();
+        let adjustments = self.current_adjustments();
+        let mut duplicate_action = None;
+        let mut edit_action = None;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(finder) = &mut self.duplicates {
+                duplicate_action = finder.show(ui);
+                return;
+            }
+            if let Some(editor) = &mut self.editor {
+                edit_action = editor.show(ui);
+                return;
+            }
+            if let Some(compare) = &mut self.compare {
+                let b = self.images.get(self.current_index).zip(self.current_image.as_ref());
+                compare.show(ui, b.map(|(path, image)| (path, &image.display_image, current_rotation)));
+                return;
+            }
+
+            let panel_rect = ui.max_rect();
+            if let Some(shown) = &mut self.current_image
+                && let Some(path) = self.images.get(self.current_index)
+            {
+                let size = shown.display_image.dimensions();
+                let (rotation, original_size) = (shown.rotation, shown.original_size);
+
+                let channel = self.channel;
+                let premultiplied = self.background == transparency::Background::Premultiplied;
+                let (derived_texture, derived_job) = (&mut self.derived_texture, &mut self.derived_job);
+                let uploaded = shown.texture.as_ref().map(|(texture, options)| (texture.id(), *options));
+                let image_rect = widget::ImageView::new(shown)
+                    .zoom(self.zoom)
+                    .filter(self.filter)
+                    .integer_scaling(self.integer_scaling)
+                    .background(self.background, &self.options.background)
+                    // Adjustments, the channel view and the premultiplied preview swap in a
+                    // texture made from the display copy
+                    .texture_override(|img, options| {
+                        if channel == histogram::Channel::All && adjustments.is_identity() && !premultiplied {
+                            return None;
+                        }
+                        let key = DerivedKey {
+                            path: path.clone(),
+                            rotation,
+                            channel,
+                            adjustments,
+                            premultiplied,
+                            options,
+                        };
+                        if let Some((_, rx)) = derived_job
+                            && let Ok(derived) = rx.try_recv()
+                        {
+                            let (done, _) = derived_job.take().unwrap();
+                            let texture = ctx.load_texture("derived_view", derived, done.options);
+                            *derived_texture = Some((done, texture));
+                        }
+                        if derived_job.is_none() && !matches!(derived_texture, Some((shown, _)) if *shown == key) {
+                            let rx = spawn_derived(ctx, img.clone(), &key);
+                            *derived_job = Some((key.clone(), rx));
+                        }
+                        // The previous result for this image stays up until the new one is ready
+                        match derived_texture {
+                            Some((shown, texture)) if shown.path == key.path && shown.rotation == key.rotation => Some(texture.id()),
+                            _ => None,
+                        }
+                    })
+                    .show(ui)
+                    .rect;
+
+                // A new upload goes back to the cache entry too, so coming back to
+                // the image doesn't upload it again
+                if shown.texture.as_ref().map(|(texture, options)| (texture.id(), *options)) != uploaded
+                    && let Some(texture) = shown.texture.clone()
+                {
+                    self.uploaded_texture = Some((path.clone(), shown.rotation, texture));
+                }
+
+                // Grid over original pixels, which the display copy may have fewer of
+                if self.pixel_grid {
+                    let (w, h) = original_size;
+                    let pixels = if rotation % 180 == 90 { (h, w) } else { (w, h) };
+                    scaling::paint_pixel_grid(&ui.painter().with_clip_rect(panel_rect), image_rect, pixels);
+                }
+
+                if let Some(overlay) = &mut self.stray_colors {
+                    overlay.show(ui, image_rect, path, size, rotation, &self.loader);
+                }
+
+                if let Some(inspector) = &mut self.inspector {
+                    inspector.show(ctx, image_rect, path, shown, &self.loader);
+                }
+
+                if let Some(overlay) = &mut self.histogram {
+                    overlay.show(ui, panel_rect, path, &self.loader);
+                }
+            } else if let Some(path) = self.images.get(self.current_index)
+                && let Some(error) = self.load_errors.get(path)
+            {
+                error_card(ui, path, error);
+            } else if self.loading_image.is_some() {
+                ui.centered_and_justified(|ui| {
+                    ui.label("Loading image...");
+                    ui.add(egui::Spinner::new());
+                });
+            } else if self.incoming.is_some() && self.images.is_empty() {
+                ui.centered_and_justified(|ui| {
+                    ui.label("Waiting for paths...");
+                    ui.add(egui::Spinner::new());
+                });
+            } else {
+                ui.centered_and_justified(|ui| {
+                    ui.label("No image loaded");
+                });
+            }
+
+            let painter = ui.painter();
+            let key_handler_status = if let Some(job) = &self.key_handler_job {
+                Some(format!("Running key handler {}...", job.key))
+            } else if self.key_handler_prefix {
+                Some("Ctrl+X: waiting for key (Esc cancels)".to_string())
+            } else if let Some((message, shown_at)) = &self.status
+                && shown_at.elapsed() < std::time::Duration::from_secs(4)
+            {
+                ctx.request_repaint_after(std::time::Duration::from_secs(4) - shown_at.elapsed());
+                Some(message.clone())
+            } else {
+                None
+            };
+            if let Some(status) = key_handler_status {
+                painter.text(
+                    panel_rect.left_bottom() + egui::vec2(8.0, -8.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    status,
+                    egui::FontId::proportional(14.0),
+                    ui.visuals().text_color(),
+                );
+            }
+
+            // Mark indicator and filter status in the top-right corner
+            let corner = panel_rect.right_top() + egui::vec2(-16.0, 16.0);
+            if self.images.get(self.current_index).is_some_and(|p| self.marked.contains(p)) {
+                painter.circle_filled(corner, 8.0, egui::Color32::from_rgb(255, 200, 0));
+            }
+            if !self.marked.is_empty() || self.marked_only {
+                let status = if self.marked_only {
+                    format!("{} marked (marked only)", self.marked.len())
+                } else {
+                    format!("{} marked", self.marked.len())
+                };
+                painter.text(
+                    corner - egui::vec2(16.0, 0.0),
+                    egui::Align2::RIGHT_CENTER,
+                    status,
+                    egui::FontId::proportional(14.0),
+                    egui::Color32::from_rgb(255, 200, 0),
+                );
+            }
+        });
+
+        if let Some(action) = duplicate_action {
+            self.handle_duplicate_action(action);
+        }
+        if let Some(e) = self.duplicates.as_mut().and_then(duplicates::DuplicateFinder::take_error) {
+            self.report_error(e);
+        }
+        if let Some(action) = edit_action {
+            self.handle_edit_action(action);
+        }
+
+        // Show delete confirmation dialog
+        if self.show_delete_confirm
+            && let Some(path) = &self.image_to_delete
+        {
+            let path_clone = path.clone();
+            let mut open = true;
+            egui::Window::new("Confirm Delete")
+                .open(&mut open)
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label(format!("Delete image: {}", path_clone.display()));
+                    ui.label("This action cannot be undone.");
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Delete").clicked() {
+                            if let Err(e) = self.delete_image(&path_clone) {
+                                self.report_error(format!("Failed to delete {}: {}", path_clone.display(), e));
+                                self.set_status(format!("Delete failed: {}", e));
+                            } else {
+                                self.update_image_list_after_delete();
+                            }
+                        }
+
+                        if ui.button("Cancel").clicked() {
+                            self.show_delete_confirm = false;
+                            self.image_to_delete = None;
+                        }
+                    });
+                });
+
+            // Close dialog if user clicked outside or pressed escape
+            if !open {
+                self.show_delete_confirm = false;
+                self.image_to_delete = None;
+            }
+        }
+
+        self.show_rename_dialog(ctx);
+        self.show_adjustments_window(ctx);
+        self.show_background_window(ctx);
+        self.error_log.show(ctx, &mut self.options.skip_broken);
+        self.show_recent_window(ctx);
+        if self.hud.open {
+            self.hud.show(ctx, &self.hud_info());
+        }
+
+        // Handle keyboard input, unless a text field has focus
+        if !ctx.wants_keyboard_input() {
+            self.handle_keys(ctx);
+        }
+
+        // Reset delete pending state if timeout (more than 1 second)
+        if self.delete_pending
+            && let Some(timestamp) = self.delete_timestamp
+            && std::time::Instant::now().duration_since(timestamp).as_millis() >= 1000
+        {
+            self.delete_pending = false;
+            self.delete_timestamp = None;
+        }
+
+        self.store_uploaded_texture();
+        self.announce_current_image();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.save_session();
+    }
+}
+
+/// What's shown in place of an image that failed to decode.
+fn error_card(ui: &mut egui::Ui, path: &std::path::Path, error: &loader::LoadError) {
+    ui.vertical_centered(|ui| {
+        ui.add_space(ui.available_height() / 3.0);
+        egui::Frame::group(ui.style()).inner_margin(16.0).show(ui, |ui| {
+            ui.set_max_width(480.0);
+            ui.heading(error.kind.name());
+            ui.label(path.display().to_string());
+            ui.label(egui::RichText::new(&error.message).weak());
+            ui.small("Ctrl+L shows all errors");
+        });
+    });
+}
+
+/// `img` turned clockwise by `rotation` degrees (a multiple of 90).
+pub fn rotate_image(img: &DynamicImage, rotation: u32) -> DynamicImage {
+    match rotation % 360 {
+        90 => img.rotate90(),
+        180 => img.rotate180(),
+        270 => img.rotate270(),
+        _ => img.clone(),
+    }
+}
+
+/// Open the viewer window on `images` and block until it's closed.
+pub fn run_window(images: Vec<PathBuf>, incoming: Option<pathlist::PathStream>, options: ViewerOptions) -> Result<(), eframe::Error> {
+    let native_options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 600.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Image Viewer",
+        native_options,
+        Box::new(move |cc| Box::new(ImageViewer::new(images, incoming, options, &cc.egui_ctx))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    #[test]
+    fn test_rotation_initialization() {
+        let img = DynamicImage::new_rgb8(100, 100);
+        let cached = CachedImage::new(img, (100, 100));
+        
+        assert_eq!(cached.rotation, 0);
+    }
+
+    #[test]
+    fn test_rotation_math() {
+        // Test rotation calculation logic
+        let mut rotation = 0;
+        
+        // Test single rotation
+        rotation = (rotation + 90) % 360;
+        assert_eq!(rotation, 90);
+        
+        // Test multiple rotations
+        rotation = (rotation + 90) % 360;
+        assert_eq!(rotation, 180);
+        
+        rotation = (rotation + 90) % 360;
+        assert_eq!(rotation, 270);
+        
+        // Test wrap-around
+        rotation = (rotation + 90) % 360;
+        assert_eq!(rotation, 0);
+        
+        // Test additional rotations
+        rotation = (rotation + 90) % 360;
+        assert_eq!(rotation, 90);
+    }
+
+    fn test_viewer(images: Vec<PathBuf>) -> ImageViewer {
+        let image_cache = cache::ImageCache::shared(1 << 20);
+        ImageViewer {
+            images,
+            current_index: 0,
+            current_image: None,
+            uploaded_texture: None,
+            loading_image: None,
+            loader: loader::Loader::new(image_cache.clone()),
+            image_cache,
+            preload_handles: HashMap::new(),
+            delete_pending: false,
+            delete_timestamp: None,
+            show_delete_confirm: false,
+            image_to_delete: None,
+            zoom: ZoomMode::Fit,
+            ipc: None,
+            announced_path: None,
+            incoming: None,
+            marked: HashSet::new(),
+            marked_only: false,
+            options: ViewerOptions::default(),
+            key_handler_prefix: false,
+            key_handler_job: None,
+            undo_stack: Vec::new(),
+            status: None,
+            rename_dialog: None,
+            pending_rekeys: Vec::new(),
+            duplicates: None,
+            compare: None,
+            inspector: None,
+            histogram: None,
+            channel: histogram::Channel::All,
+            derived_texture: None,
+            derived_job: None,
+            background: transparency::Background::default(),
+            stray_colors: None,
+            filter: scaling::FilterMode::default(),
+            integer_scaling: false,
+            pixel_grid: false,
+            editor: None,
+            adjustments: adjust::Adjustments::default(),
+            image_adjustments: HashMap::new(),
+            adjust_per_image: false,
+            show_adjustments: false,
+            show_background_settings: false,
+            montage_job: None,
+            load_errors: HashMap::new(),
+            error_log: errorlog::ErrorLog::default(),
+            skip_forward: None,
+            hud: hud::Hud::default(),
+            given_order: HashMap::new(),
+            sort: session::SortOrder::default(),
+            rotations: HashMap::new(),
+            sessions: None,
+            recent: None,
+            prompt: None,
+            last_search: None,
+        }
+    }
+
+    #[test]
+    fn test_rotation_with_no_image() {
+        let mut viewer = test_viewer(Vec::new());
+
+        // This should not panic
+        viewer.rotate_current_image();
+    }
+
+    #[test]
+    fn test_finished_load_is_picked_up_and_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.png");
+        image::RgbImage::new(4, 2).save(&path).unwrap();
+
+        let mut viewer = test_viewer(vec![path.clone()]);
+        viewer.load_current_image();
+        for _ in 0..500 {
+            viewer.poll_background();
+            if viewer.loading_image.is_none() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(viewer.current_image.as_ref().map(|image| image.original_size), Some((4, 2)));
+
+        viewer.rotate_current_image();
+        assert_eq!(viewer.current_rotation(), 90);
+        assert_eq!(viewer.image_cache.lock().unwrap().peek(&path).map(|cached| cached.rotation), Some(90));
+    }
+
+    #[test]
+    fn test_filter_change_drops_display_copies() {
+        let mut viewer = test_viewer(vec![PathBuf::from("a.png")]);
+        viewer.error_log.echo = false;
+        let copy = CachedImage::new(DynamicImage::new_rgb8(2, 2), (2, 2));
+        viewer.image_cache.lock().unwrap().put(PathBuf::from("a.png"), copy);
+
+        // Lanczos shrinks the same way as auto
+        viewer.set_filter(scaling::FilterMode::Lanczos);
+        assert_eq!(viewer.image_cache.lock().unwrap().len(), 1);
+        viewer.set_filter(scaling::FilterMode::Nearest);
+        assert!(viewer.image_cache.lock().unwrap().is_empty());
+        assert!(viewer.loading_image.is_some());
+    }
+
+    #[test]
+    fn test_skips_broken_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let images: Vec<PathBuf> = ["a.png", "bad.png", "c.png"].iter().map(|name| dir.path().join(name)).collect();
+        image::RgbImage::new(4, 2).save(&images[0]).unwrap();
+        std::fs::write(&images[1], b"not an image").unwrap();
+        image::RgbImage::new(2, 2).save(&images[2]).unwrap();
+
+        let mut viewer = test_viewer(images.clone());
+        viewer.options.skip_broken = true;
+        viewer.error_log.echo = false;
+        viewer.next_image();
+        for _ in 0..500 {
+            viewer.poll_background();
+            if viewer.current_image.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(viewer.current_index, 2);
+        assert_eq!(viewer.load_errors.get(&images[1]).map(|e| e.kind), Some(loader::ErrorKind::Corrupt));
+        assert_eq!(viewer.error_log.messages().count(), 1);
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let images: Vec<PathBuf> = ["c.png", "a.png", "b.png"].iter().map(|name| dir.path().join(name)).collect();
+        let fresh = || {
+            let mut viewer = test_viewer(images.clone());
+            viewer.sessions = Some(session::SessionStore::at(dir.path().join("state")));
+            viewer.options.session_dir = Some(dir.path().to_path_buf());
+            viewer.options.restore_session = true;
+            viewer.record_given_order();
+            viewer
+        };
+
+        let mut viewer = fresh();
+        viewer.restore_session();
+        viewer.cycle_sort();
+        assert_eq!(viewer.images, [dir.path().join("a.png"), dir.path().join("b.png"), dir.path().join("c.png")]);
+        viewer.current_index = 1;
+        viewer.toggle_mark_current();
+        viewer.rotations.insert(dir.path().join("c.png"), 270);
+        viewer.zoom = ZoomMode::Scale(2.0);
+        viewer.save_session();
+
+        let mut viewer = fresh();
+        viewer.restore_session();
+        assert_eq!(viewer.sort, session::SortOrder::Name);
+        assert_eq!(viewer.images[viewer.current_index], dir.path().join("b.png"));
+        assert!(viewer.marked.contains(&dir.path().join("b.png")));
+        assert_eq!(viewer.rotations.get(&dir.path().join("c.png")), Some(&270));
+        assert!(matches!(viewer.zoom, ZoomMode::Scale(f) if f == 2.0));
+        viewer.cycle_sort();
+        viewer.cycle_sort();
+        assert_eq!(viewer.images, images);
+
+        let mut viewer = fresh();
+        viewer.options.restore_session = false;
+        viewer.restore_session();
+        assert_eq!((viewer.current_index, viewer.sort), (0, session::SortOrder::Given));
+        assert!(viewer.marked.is_empty());
+        assert_eq!(viewer.sessions.as_ref().unwrap().recent(), vec![dir.path().canonicalize().unwrap()]);
+
+        // Opening a folder without a session starts clean
+        let other = dir.path().join("other");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(other.join("x.png"), b"").unwrap();
+        let mut viewer = fresh();
+        viewer.restore_session();
+        viewer.marked_only = true;
+        viewer.open_path(other.clone()).unwrap();
+        assert_eq!(viewer.images, [other.join("x.png")]);
+        assert!(viewer.marked.is_empty() && !viewer.marked_only);
+        assert!(matches!(viewer.zoom, ZoomMode::Fit));
+        assert_eq!(viewer.sort, session::SortOrder::Given);
+    }
+
+    /// Send each character of `keys` the way egui does, a key press then
+    /// its text.
+    fn type_keys(viewer: &mut ImageViewer, ctx: &egui::Context, keys: &str) {
+        for c in keys.chars() {
+            let modifiers = if c.is_uppercase() { egui::Modifiers::SHIFT } else { egui::Modifiers::NONE };
+            let key = match c {
+                '\n' => Some(egui::Key::Enter),
+                '\x1b' => Some(egui::Key::Escape),
+                _ => egui::Key::from_name(&c.to_string()),
+            };
+            let mut events: Vec<egui::Event> = key
+                .map(|key| egui::Event::Key { key, physical_key: None, pressed: true, repeat: false, modifiers })
+                .into_iter()
+                .collect();
+            if !c.is_control() {
+                events.push(egui::Event::Text(c.to_string()));
+            }
+            let _ = ctx.run(egui::RawInput { modifiers, events, ..Default::default() }, |ctx| viewer.handle_keys(ctx));
+        }
+    }
+
+    #[test]
+    fn test_search_and_jump() {
+        let images: Vec<PathBuf> = ["shots/cat.png", "shots/dog.png", "shots/cat_2.png", "shots/bird.png"].iter().map(PathBuf::from).collect();
+        let mut viewer = test_viewer(images);
+        viewer.error_log.echo = false;
+        let ctx = egui::Context::default();
+        let typing = |viewer: &mut ImageViewer, keys: &str| type_keys(viewer, &ctx, keys);
+
+        typing(&mut viewer, "/dog");
+        assert_eq!(viewer.current_index, 1);
+        typing(&mut viewer, "\n/cat\n");
+        assert_eq!((viewer.current_index, viewer.last_search.as_deref()), (0, Some("cat")));
+        typing(&mut viewer, "n");
+        assert_eq!(viewer.current_index, 2);
+        typing(&mut viewer, "n");
+        assert_eq!(viewer.current_index, 0);
+        typing(&mut viewer, "N");
+        assert_eq!(viewer.current_index, 2);
+
+        typing(&mut viewer, "G");
+        assert_eq!(viewer.current_index, 3);
+        typing(&mut viewer, "g:2\n");
+        assert_eq!(viewer.current_index, 1);
+        typing(&mut viewer, ":9\n");
+        assert_eq!(viewer.current_index, 1);
+        assert!(viewer.status.as_ref().is_some_and(|(message, _)| message == "No image 9 of 4"));
+
+        // Keys type into the prompt instead of doing their usual thing, and
+        // Esc goes back to where the search started
+        typing(&mut viewer, "/bjq");
+        assert!(viewer.prompt.is_some());
+        typing(&mut viewer, "\x1b");
+        assert_eq!((viewer.current_index, viewer.prompt.is_none()), (1, true));
+    }
+
+    #[test]
+    fn test_duplicates_view_holds_keys() {
+        let images: Vec<PathBuf> = ["a.png", "b.png"].iter().map(PathBuf::from).collect();
+        let mut viewer = test_viewer(images);
+        viewer.error_log.echo = false;
+        let ctx = egui::Context::default();
+        viewer.duplicates = Some(duplicates::DuplicateFinder::start(&[], &ctx));
+        type_keys(&mut viewer, &ctx, "jdd");
+        assert_eq!((viewer.current_index, viewer.show_delete_confirm), (0, false));
+        type_keys(&mut viewer, &ctx, "\x1bj");
+        assert_eq!((viewer.current_index, viewer.duplicates.is_none()), (1, true));
+    }
+
+    #[test]
+    fn test_marks_and_marked_only_navigation() {
+        let images: Vec<PathBuf> = ["a.png", "b.png", "c.png", "d.png"].iter().map(PathBuf::from).collect();
+        let mut viewer = test_viewer(images);
+
+        viewer.toggle_mark_current();
+        viewer.current_index = 2;
+        viewer.toggle_mark_current();
+        assert_eq!(viewer.marked_paths(), vec![&PathBuf::from("a.png"), &PathBuf::from("c.png")]);
+
+        viewer.marked_only = true;
+        assert_eq!(viewer.neighbor_index(true), Some(0));
+        assert_eq!(viewer.neighbor_index(false), Some(0));
+
+        viewer.invert_marks();
+        assert_eq!(viewer.marked_paths(), vec![&PathBuf::from("b.png"), &PathBuf::from("d.png")]);
+        assert_eq!(viewer.neighbor_index(true), Some(3));
+
+        let mut out = Vec::new();
+        viewer.write_marked(&mut out, b'\0').unwrap();
+        assert_eq!(out, b"b.png\0d.png\0");
+
+        viewer.marked.clear();
+        assert_eq!(viewer.neighbor_index(true), None);
+    }
+
+    #[test]
+    #[allow(clippy::identity_op)]
+    fn test_aspect_ratio_calculation_after_rotation() {
+        // Test that aspect ratio calculation correctly handles rotated dimensions
+        let (orig_w, orig_h) = (1920, 1080); // Landscape image
+        
+        // Test 0° rotation (no change)
+        let (display_w, display_h) = match 0 % 360 {
+            90 | 270 => (orig_h, orig_w),
+            _ => (orig_w, orig_h),
+        };
+        let aspect_0 = display_w as f32 / display_h as f32;
+        assert!((aspect_0 - 1920.0/1080.0).abs() < 0.001);
+        
+        // Test 90° rotation (landscape becomes portrait)
+        let (display_w, display_h) = match 90 % 360 {
+            90 | 270 => (orig_h, orig_w),
+            _ => (orig_w, orig_h),
+        };
+        let aspect_90 = display_w as f32 / display_h as f32;
+        assert!((aspect_90 - 1080.0/1920.0).abs() < 0.001);
+        
+        // Test 180° rotation (no change to aspect ratio)
+        let (display_w, display_h) = match 180 % 360 {
+            90 | 270 => (orig_h, orig_w),
+            _ => (orig_w, orig_h),
+        };
+        let aspect_180 = display_w as f32 / display_h as f32;
+        assert!((aspect_180 - 1920.0/1080.0).abs() < 0.001);
+        
+        // Test 270° rotation (landscape becomes portrait)
+        let (display_w, display_h) = match 270 % 360 {
+            90 | 270 => (orig_h, orig_w),
+            _ => (orig_w, orig_h),
+        };
+        let aspect_270 = display_w as f32 / display_h as f32;
+        assert!((aspect_270 - 1080.0/1920.0).abs() < 0.001);
+        
+        // Verify that 90° and 270° rotations have the same aspect ratio
+        assert!((aspect_90 - aspect_270).abs() < 0.001);
+        
+        // Verify that 0° and 180° rotations have the same aspect ratio
+        assert!((aspect_0 - aspect_180).abs() < 0.001);
+        
+        // Verify that 90° rotation is different from 0° rotation
+        assert!((aspect_0 - aspect_90).abs() > 0.001);
+    }
+}
\ No newline at end of file
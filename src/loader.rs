@@ -0,0 +1,560 @@
+use crate::cache::{CachedImage, SharedCache};
+use eframe::egui;
+use futures::channel::oneshot;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Longest side of the cached display copies.
+pub const DISPLAY_MAX_SIZE: u32 = 1920;
+
+/// Upper bound on decode threads, each of which may hold a full-size image.
+const MAX_WORKERS: usize = 4;
+
+/// Decode timings kept for [`LoaderStats`].
+const RECENT_DECODES: usize = 100;
+
+/// Priority of full-resolution jobs for overlays, behind the current image
+/// and its preloads.
+pub const FULL_PRIORITY: u32 = 1000;
+
+/// Shrink `img` so that neither side exceeds `max_size`.
+pub fn resize_for_display(img: &DynamicImage, max_size: u32, filter: FilterType) -> DynamicImage {
+    let (w, h) = img.dimensions();
+    let max_size = max_size as f32;
+    let scale = if w > h {
+        max_size / w as f32
+    } else {
+        max_size / h as f32
+    }.min(1.0);
+
+    let new_w = (w as f32 * scale) as u32;
+    let new_h = (h as f32 * scale) as u32;
+
+    let _span = tracing::debug_span!("resize", from = ?(w, h), to = ?(new_w, new_h)).entered();
+    img.resize(new_w, new_h, filter)
+}
+
+/// Why an image couldn't be shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    Unsupported,
+    /// The file ends before the image does
+    Truncated,
+    /// Data the decoder can't make sense of
+    Corrupt,
+    /// Other read errors and decoder limits
+    Other,
+    /// Dropped before it finished
+    Cancelled,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "File not found",
+            ErrorKind::PermissionDenied => "Permission denied",
+            ErrorKind::Unsupported => "Unsupported format",
+            ErrorKind::Truncated => "Truncated file",
+            ErrorKind::Corrupt => "Corrupt image",
+            ErrorKind::Other => "Read error",
+            ErrorKind::Cancelled => "Cancelled",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadError {
+    pub kind: ErrorKind,
+    /// The decoder's own description
+    pub message: String,
+}
+
+impl LoadError {
+    fn cancelled() -> Self {
+        Self {
+            kind: ErrorKind::Cancelled,
+            message: "decode was cancelled".to_string(),
+        }
+    }
+}
+
+impl From<image::ImageError> for LoadError {
+    fn from(error: image::ImageError) -> Self {
+        use image::ImageError;
+        let kind = match &error {
+            ImageError::IoError(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                std::io::ErrorKind::UnexpectedEof => ErrorKind::Truncated,
+                _ => ErrorKind::Other,
+            },
+            ImageError::Unsupported(_) => ErrorKind::Unsupported,
+            ImageError::Decoding(_) => ErrorKind::Corrupt,
+            _ => ErrorKind::Other,
+        };
+        Self {
+            kind,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)
+    }
+}
+
+/// The file's bytes as the decoder reads them, noting whether it asked for
+/// more after reaching the end. Decoders report that in different ways,
+/// often as a generic decoding error.
+struct Input<'a> {
+    bytes: std::io::Cursor<&'a [u8]>,
+    ran_out: bool,
+}
+
+impl std::io::Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.bytes.read(buf)?;
+        self.ran_out |= n == 0 && !buf.is_empty();
+        Ok(n)
+    }
+}
+
+impl std::io::BufRead for Input<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let buf = self.bytes.fill_buf()?;
+        self.ran_out |= buf.is_empty();
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.bytes.consume(amount);
+    }
+}
+
+impl std::io::Seek for Input<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.bytes.seek(pos)
+    }
+}
+
+/// Read and decode `path`, as separate steps so each shows up in traces.
+/// The format comes from the extension, like `image::open`.
+fn open(path: &Path) -> Result<DynamicImage, LoadError> {
+    let format = image::ImageFormat::from_path(path)?;
+    let bytes = {
+        let _span = tracing::debug_span!("read", path = %path.display()).entered();
+        std::fs::read(path).map_err(image::ImageError::IoError)?
+    };
+    let _span = tracing::debug_span!("decode", path = %path.display(), bytes = bytes.len()).entered();
+    let mut input = Input {
+        bytes: std::io::Cursor::new(&bytes),
+        ran_out: false,
+    };
+    image::load(&mut input, format).map_err(|e| {
+        let mut error = LoadError::from(e);
+        if error.kind == ErrorKind::Corrupt && input.ran_out {
+            error.kind = ErrorKind::Truncated;
+        }
+        error
+    })
+}
+
+/// Decode `path` into a display copy no larger than `max_size`.
+pub fn decode(path: &Path, max_size: u32, filter: FilterType) -> Result<CachedImage, LoadError> {
+    let image = open(path)?;
+    Ok(CachedImage::new(resize_for_display(&image, max_size, filter), image.dimensions()))
+}
+
+/// Fully decode each of `paths` on `jobs` threads, returning the ones that
+/// fail in list order.
+pub fn find_broken(paths: &[PathBuf], jobs: usize) -> Vec<(PathBuf, LoadError)> {
+    let next = AtomicUsize::new(0);
+    let mut broken = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, paths.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Err(e) = open(path) {
+                        broken.lock().unwrap().push((path.clone(), e));
+                    }
+                }
+            });
+        }
+    });
+    let broken = broken.get_mut().unwrap();
+    broken.sort_by_key(|(path, _)| paths.iter().position(|p| p == path));
+    std::mem::take(broken)
+}
+
+/// State shared between a queued decode and its handle.
+#[derive(Default)]
+struct JobState {
+    priority: AtomicU32,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+/// What to do with a decoded image.
+enum Work {
+    /// Cache a display copy resized with `filter`
+    Display {
+        filter: FilterType,
+        result: oneshot::Sender<Result<CachedImage, LoadError>>,
+    },
+    /// Hand the full-resolution image to a function, without caching
+    Full(Box<dyn FnOnce(Result<DynamicImage, LoadError>) + Send>),
+}
+
+struct Job {
+    path: PathBuf,
+    work: Work,
+    /// Submission order, so equal priorities are served first come first served
+    seq: u64,
+    state: Arc<JobState>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Vec<Job>,
+    next_seq: u64,
+    shutdown: bool,
+}
+
+impl Queue {
+    /// Take the most urgent job, dropping cancelled ones on the way.
+    fn pop(&mut self) -> Option<Job> {
+        self.jobs.retain(|job| !job.state.cancelled.load(Ordering::Relaxed));
+        let (index, _) = self
+            .jobs
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, job)| (job.state.priority.load(Ordering::Relaxed), job.seq))?;
+        Some(self.jobs.swap_remove(index))
+    }
+}
+
+struct Shared {
+    cache: SharedCache,
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    /// Repainted whenever a decode finishes
+    ctx: OnceLock<egui::Context>,
+    running: AtomicUsize,
+    /// How long the latest successful decodes took, oldest first
+    recent: Mutex<VecDeque<Duration>>,
+}
+
+impl Shared {
+    fn work(&self, max_size: u32) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if queue.shutdown {
+                        return;
+                    }
+                    if let Some(job) = queue.pop() {
+                        break job;
+                    }
+                    queue = self.ready.wait(queue).unwrap();
+                }
+            };
+            self.running.fetch_add(1, Ordering::Relaxed);
+            match job.work {
+                Work::Display { filter, result } => {
+                    let _ = result.send(self.decode(&job.path, &job.state, filter, max_size));
+                }
+                Work::Full(task) => task(open_unless_cancelled(&job.path, &job.state)),
+            }
+            self.running.fetch_sub(1, Ordering::Relaxed);
+            job.state.finished.store(true, Ordering::Relaxed);
+            if let Some(ctx) = self.ctx.get() {
+                ctx.request_repaint();
+            }
+        }
+    }
+
+    /// Decode and cache `path`, checking between the slow steps whether
+    /// it's still wanted.
+    fn decode(&self, path: &Path, state: &JobState, filter: FilterType, max_size: u32) -> Result<CachedImage, LoadError> {
+        let started = Instant::now();
+        let image = open_unless_cancelled(path, state)?;
+        let cached = CachedImage::new(resize_for_display(&image, max_size, filter), image.dimensions());
+        if state.cancelled.load(Ordering::Relaxed) {
+            return Err(LoadError::cancelled());
+        }
+        self.cache.lock().unwrap().put(path.to_path_buf(), cached.clone());
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_DECODES {
+            recent.pop_front();
+        }
+        recent.push_back(started.elapsed());
+        Ok(cached)
+    }
+}
+
+/// Decode `path` if the job is still wanted before and after.
+fn open_unless_cancelled(path: &Path, state: &JobState) -> Result<DynamicImage, LoadError> {
+    let cancelled = || state.cancelled.load(Ordering::Relaxed);
+    if cancelled() {
+        return Err(LoadError::cancelled());
+    }
+    let image = open(path)?;
+    if cancelled() {
+        return Err(LoadError::cancelled());
+    }
+    Ok(image)
+}
+
+/// Stops the workers once the last `Loader` clone is gone.
+struct Pool(Arc<Shared>);
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().shutdown = true;
+        self.0.ready.notify_all();
+    }
+}
+
+/// A snapshot of the loader's work.
+#[derive(Clone, Debug, Default)]
+pub struct LoaderStats {
+    /// Decodes waiting for a thread
+    pub queued: usize,
+    pub running: usize,
+    /// Read, decode and resize time of the latest decodes, oldest first
+    pub recent: Vec<Duration>,
+}
+
+/// Decodes images into a shared cache on a fixed set of threads, most
+/// urgent first.
+#[derive(Clone)]
+pub struct Loader {
+    shared: Arc<Shared>,
+    _pool: Arc<Pool>,
+}
+
+/// A queued or running decode, resolving to a copy of what was cached, or
+/// for [`Loader::load_full`] to what was made from the full image.
+/// Dropping the handle lets it finish.
+pub struct LoadHandle<T = CachedImage> {
+    path: PathBuf,
+    state: Arc<JobState>,
+    result: oneshot::Receiver<Result<T, LoadError>>,
+}
+
+impl<T> LoadHandle<T> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Relaxed)
+    }
+
+    /// The result if the decode is done, without waiting.
+    pub fn try_take(&mut self) -> Option<Result<T, LoadError>> {
+        match self.result.try_recv() {
+            Ok(result) => result,
+            // The loader shut down first
+            Err(oneshot::Canceled) => Some(Err(LoadError::cancelled())),
+        }
+    }
+
+    /// Drop the decode. One that already started stops at the next step,
+    /// and nothing it produced is cached.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Move the decode up or down the queue; 0 comes first.
+    pub fn set_priority(&self, priority: u32) {
+        self.state.priority.store(priority, Ordering::Relaxed);
+    }
+}
+
+impl<T> Future for LoadHandle<T> {
+    type Output = Result<T, LoadError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.result).poll(cx).map(|result| result.unwrap_or_else(|_| Err(LoadError::cancelled())))
+    }
+}
+
+impl Loader {
+    /// A loader with one thread per core, up to a few.
+    pub fn new(cache: SharedCache) -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_workers(cache, cores.min(MAX_WORKERS), DISPLAY_MAX_SIZE)
+    }
+
+    /// A loader with `workers` decode threads making display copies no
+    /// larger than `max_size`.
+    pub fn with_workers(cache: SharedCache, workers: usize, max_size: u32) -> Self {
+        let shared = Arc::new(Shared {
+            cache,
+            queue: Mutex::new(Queue::default()),
+            ready: Condvar::new(),
+            ctx: OnceLock::new(),
+            running: AtomicUsize::new(0),
+            recent: Mutex::new(VecDeque::new()),
+        });
+        for _ in 0..workers.max(1) {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("img-decode".to_string())
+                .spawn(move || shared.work(max_size))
+                .expect("failed to spawn decode thread");
+        }
+        Self {
+            _pool: Arc::new(Pool(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Repaint `ctx` whenever a decode finishes.
+    pub fn attach(&self, ctx: &egui::Context) {
+        let _ = self.shared.ctx.set(ctx.clone());
+    }
+
+    pub fn cache(&self) -> &SharedCache {
+        &self.shared.cache
+    }
+
+    pub fn stats(&self) -> LoaderStats {
+        let queued = {
+            let queue = self.shared.queue.lock().unwrap();
+            queue.jobs.iter().filter(|job| !job.state.cancelled.load(Ordering::Relaxed)).count()
+        };
+        LoaderStats {
+            queued,
+            running: self.shared.running.load(Ordering::Relaxed),
+            recent: self.shared.recent.lock().unwrap().iter().copied().collect(),
+        }
+    }
+
+    /// Queue `path` for decoding, resizing with `filter`. Lower `priority`
+    /// goes first.
+    pub fn load(&self, path: PathBuf, filter: FilterType, priority: u32) -> LoadHandle {
+        let (tx, rx) = oneshot::channel();
+        let state = self.submit(path.clone(), Work::Display { filter, result: tx }, priority);
+        LoadHandle { path, state, result: rx }
+    }
+
+    /// Queue a full-resolution decode of `path`, handing the image to `f`
+    /// on the decode thread. Nothing is cached; cancel the handle once the
+    /// result is no longer wanted.
+    pub fn load_full<T: Send + 'static>(
+        &self,
+        path: PathBuf,
+        priority: u32,
+        f: impl FnOnce(DynamicImage) -> T + Send + 'static,
+    ) -> LoadHandle<T> {
+        let (tx, rx) = oneshot::channel();
+        let task = Box::new(move |image: Result<DynamicImage, LoadError>| {
+            let _ = tx.send(image.map(f));
+        });
+        let state = self.submit(path.clone(), Work::Full(task), priority);
+        LoadHandle { path, state, result: rx }
+    }
+
+    fn submit(&self, path: PathBuf, work: Work, priority: u32) -> Arc<JobState> {
+        let state = Arc::new(JobState {
+            priority: AtomicU32::new(priority),
+            ..Default::default()
+        });
+        let mut queue = self.shared.queue.lock().unwrap();
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.jobs.push(Job {
+            path,
+            work,
+            seq,
+            state: state.clone(),
+        });
+        self.shared.ready.notify_one();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::ImageCache;
+
+    #[test]
+    fn test_queue_order_and_loading() {
+        let mut queue = Queue::default();
+        let mut handles = Vec::new();
+        for (seq, priority) in [3, 0, 1, 1].into_iter().enumerate() {
+            let state = Arc::new(JobState {
+                priority: AtomicU32::new(priority),
+                ..Default::default()
+            });
+            let (tx, rx) = oneshot::channel();
+            queue.jobs.push(Job {
+                path: PathBuf::from(seq.to_string()),
+                work: Work::Display {
+                    filter: FilterType::Nearest,
+                    result: tx,
+                },
+                seq: seq as u64,
+                state: state.clone(),
+            });
+            handles.push((state, rx));
+        }
+        handles[1].0.cancelled.store(true, Ordering::Relaxed);
+        handles[0].0.priority.store(1, Ordering::Relaxed);
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|job| job.path).collect();
+        assert_eq!(order, ["0", "2", "3"].map(PathBuf::from));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.png");
+        image::RgbImage::new(40, 20).save(&path).unwrap();
+        let loader = Loader::with_workers(ImageCache::shared(1 << 20), 2, 10);
+        let loaded = futures::executor::block_on(loader.load(path.clone(), FilterType::Triangle, 0)).unwrap();
+        assert_eq!((loaded.display_image.dimensions(), loaded.original_size), ((10, 5), (40, 20)));
+        assert!(loader.cache().lock().unwrap().contains(&path));
+        assert_eq!((loader.stats().queued, loader.stats().recent.len()), (0, 1));
+        let size = futures::executor::block_on(loader.load_full(path.clone(), FULL_PRIORITY, |image| image.dimensions()));
+        assert_eq!(size, Ok((40, 20)));
+
+        let mut missing = loader.load(dir.path().join("missing.png"), FilterType::Triangle, 0);
+        let result = loop {
+            if let Some(result) = missing.try_take() {
+                break result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(result.err().map(|e| e.kind), Some(ErrorKind::NotFound));
+
+        let png = std::fs::read(&path).unwrap();
+        std::fs::write(dir.path().join("cut.png"), &png[..png.len() / 2]).unwrap();
+        std::fs::write(dir.path().join("junk.png"), b"not an image").unwrap();
+        std::fs::write(dir.path().join("notes.xyz"), b"").unwrap();
+        let paths = ["junk.png", "a.png", "cut.png", "notes.xyz"].map(|name| dir.path().join(name));
+        let broken: Vec<_> = find_broken(&paths, 2).into_iter().map(|(path, e)| (path, e.kind)).collect();
+        assert_eq!(
+            broken,
+            vec![
+                (paths[0].clone(), ErrorKind::Corrupt),
+                (paths[2].clone(), ErrorKind::Truncated),
+                (paths[3].clone(), ErrorKind::Unsupported),
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+pub fn read_exif(path: &Path) -> Option<exif::Exif> {
+    let file = std::fs::File::open(path).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()
+}
+
+/// An EXIF timestamp, `YYYY:MM:DD HH:MM:SS` in the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExifDate {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl ExifDate {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (date, time) = text.trim().split_once(' ')?;
+        let mut date = date.split(':').map(|v| v.parse::<u32>().ok());
+        let mut time = time.split(':').map(|v| v.parse::<u32>().ok());
+        Some(Self {
+            year: date.next()??,
+            month: date.next()??,
+            day: date.next()??,
+            hour: time.next()??,
+            minute: time.next()??,
+            second: time.next()??,
+        })
+    }
+
+    /// Format with the strftime subset `%Y %m %d %H %M %S %%`.
+    pub fn format(&self, pattern: &str) -> String {
+        let mut out = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.minute)),
+                Some('S') => out.push_str(&format!("{:02}", self.second)),
+                Some(other) => {
+                    out.push('%');
+                    if other != '%' {
+                        out.push(other);
+                    }
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+fn ascii_field(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(values) => values.first().map(|v| String::from_utf8_lossy(v).into_owned()),
+        _ => None,
+    }
+}
+
+/// When the picture was taken, falling back to the last modification time
+/// recorded by the camera.
+pub fn date_taken(exif: &exif::Exif) -> Option<ExifDate> {
+    ascii_field(exif, exif::Tag::DateTimeOriginal)
+        .or_else(|| ascii_field(exif, exif::Tag::DateTime))
+        .and_then(|text| ExifDate::parse(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exif_date() {
+        let date = ExifDate::parse("2023:04:05 12:34:56").unwrap();
+        assert_eq!(date.format("%Y%m%d"), "20230405");
+        assert_eq!(date.format("%Y-%m-%d_%H%M%S 100%%"), "2023-04-05_123456 100%");
+        assert!(ExifDate::parse("    :  :     :  :  ").is_none());
+    }
+}
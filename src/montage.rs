@@ -0,0 +1,251 @@
+use crate::export::{self, EncodeOptions, Format};
+use crate::scanner;
+use crate::transparency::Rgb;
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use eframe::egui;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const CAPTION_SIZE: f32 = 14.0;
+
+/// How thumbnails are arranged on a sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub cols: u32,
+    /// Rows per sheet, so large sets are split over several sheets
+    pub rows: u32,
+    /// Thumbnail size in pixels
+    pub tile: u32,
+    pub spacing: u32,
+    pub labels: bool,
+    pub background: Rgb,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            cols: 6,
+            rows: 8,
+            tile: 256,
+            spacing: 8,
+            labels: true,
+            background: Rgb([0x20, 0x20, 0x20]),
+        }
+    }
+}
+
+impl Layout {
+    fn caption_height(&self) -> u32 {
+        if self.labels { CAPTION_SIZE as u32 + 6 } else { 0 }
+    }
+
+    fn per_sheet(&self) -> usize {
+        (self.cols * self.rows).max(1) as usize
+    }
+
+    /// Number of sheets `count` images are laid out on.
+    pub fn sheet_count(&self, count: usize) -> usize {
+        count.div_ceil(self.per_sheet()).max(1)
+    }
+}
+
+/// Thumbnails no bigger than `tile`, decoded on all cores.
+fn thumbnails(paths: &[PathBuf], tile: u32) -> Vec<Result<DynamicImage, String>> {
+    let jobs = std::thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<DynamicImage, String>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(i) else { break };
+                        let thumb = image::open(path).map(|img| img.thumbnail(tile, tile)).map_err(|e| e.to_string());
+                        done.push((i, thumb));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap_or_default()).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, thumb)| thumb).collect()
+}
+
+/// The proportional font egui ships with, used for captions.
+fn caption_font() -> Option<FontRef<'static>> {
+    let fonts = egui::FontDefinitions::default();
+    let std::borrow::Cow::Borrowed(bytes) = &fonts.font_data.get("Ubuntu-Light")?.font else {
+        return None;
+    };
+    FontRef::try_from_slice(bytes).ok()
+}
+
+/// Draw `text` with its top-left corner at `(x, y)`, cut short with an
+/// ellipsis if it's wider than `max_width`.
+fn draw_text(sheet: &mut RgbaImage, font: &FontRef, text: &str, x: f32, y: f32, max_width: f32, color: Rgba<u8>) {
+    let font = font.as_scaled(PxScale::from(CAPTION_SIZE));
+    let width = |text: &str| text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum::<f32>();
+    let mut text = text.to_string();
+    if width(&text) > max_width {
+        while !text.is_empty() && width(&text) + width("…") > max_width {
+            text.pop();
+        }
+        text.push('…');
+    }
+
+    let mut caret = x + ((max_width - width(&text)) / 2.0).max(0.0);
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        let glyph = id.with_scale_and_position(font.scale(), ab_glyph::point(caret, y + font.ascent()));
+        caret += font.h_advance(id);
+        let Some(outline) = font.outline_glyph(glyph) else { continue };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+            if px < 0 || py < 0 || px >= sheet.width() as i64 || py >= sheet.height() as i64 {
+                return;
+            }
+            let pixel = sheet.get_pixel_mut(px as u32, py as u32);
+            for channel in 0..3 {
+                let under = pixel.0[channel] as f32;
+                pixel.0[channel] = (under + (color.0[channel] as f32 - under) * coverage.min(1.0)).round() as u8;
+            }
+        });
+    }
+}
+
+/// Lay out thumbnails with captions on sheets of `layout.cols` by
+/// `layout.rows`. Returns the sheets and an error for each file that
+/// couldn't be decoded; those get an empty tile.
+pub fn render(paths: &[PathBuf], layout: &Layout) -> (Vec<RgbaImage>, Vec<String>) {
+    let mut errors = Vec::new();
+    let sheets = paths.chunks(layout.per_sheet()).map(|page| render_sheet(page, layout, &mut errors)).collect();
+    (sheets, errors)
+}
+
+/// One sheet with `paths`, which fit on it. Only this sheet's thumbnails
+/// are held in memory.
+fn render_sheet(paths: &[PathBuf], layout: &Layout, errors: &mut Vec<String>) -> RgbaImage {
+    let thumbs = thumbnails(paths, layout.tile);
+    let font = if layout.labels { caption_font() } else { None };
+    let Rgb([r, g, b]) = layout.background;
+    let background = Rgba([r, g, b, 255]);
+    let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+    let text_color = if luma > 128.0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) };
+
+    let cell_w = layout.tile + layout.spacing;
+    let cell_h = layout.tile + layout.caption_height() + layout.spacing;
+    let cols = layout.cols.min(paths.len() as u32).max(1);
+    let rows = (paths.len() as u32).div_ceil(layout.cols);
+    let mut sheet = RgbaImage::from_pixel(
+        cols * cell_w + layout.spacing,
+        rows * cell_h + layout.spacing,
+        background,
+    );
+
+    for (i, (path, thumb)) in paths.iter().zip(thumbs).enumerate() {
+        let (col, row) = (i as u32 % layout.cols, i as u32 / layout.cols);
+        let (x, y) = (layout.spacing + col * cell_w, layout.spacing + row * cell_h);
+        match thumb {
+            Ok(thumb) => {
+                // Centered in the tile, alpha blended onto the background
+                let ox = x + (layout.tile - thumb.width()) / 2;
+                let oy = y + (layout.tile - thumb.height()) / 2;
+                image::imageops::overlay(&mut sheet, &thumb.to_rgba8(), ox as i64, oy as i64);
+            }
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+        if let Some(font) = &font {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            draw_text(&mut sheet, font, &name, x as f32, (y + layout.tile + 3) as f32, layout.tile as f32, text_color);
+        }
+    }
+    sheet
+}
+
+/// File names for `count` sheets: `out` itself for one, `stem-N.ext` for more.
+pub fn sheet_paths(out: &Path, count: usize) -> Vec<PathBuf> {
+    if count == 1 {
+        return vec![out.to_path_buf()];
+    }
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = out.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..=count).map(|n| out.with_file_name(format!("{}-{}{}", stem, n, ext))).collect()
+}
+
+/// Render and save the sheets. Returns the files written and the errors
+/// for images that couldn't be read or sheets that couldn't be saved.
+pub fn export(paths: &[PathBuf], layout: &Layout, out: &Path) -> (Vec<PathBuf>, Vec<String>) {
+    let Some(format) = Format::from_path(out) else {
+        return (Vec::new(), vec![format!("{}: unsupported extension", out.display())]);
+    };
+    // Saved as they're rendered, so only one sheet is in memory at a time
+    let mut errors = Vec::new();
+    let mut written = Vec::new();
+    for (page, path) in paths.chunks(layout.per_sheet()).zip(sheet_paths(out, layout.sheet_count(paths.len()))) {
+        let sheet = render_sheet(page, layout, &mut errors);
+        match export::save(&DynamicImage::ImageRgba8(sheet), &path, &EncodeOptions::new(format)) {
+            Ok(()) => written.push(path),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    (written, errors)
+}
+
+/// `img montage`; returns the exit code.
+pub fn run(inputs: &[PathBuf], layout: &Layout, out: &Path) -> i32 {
+    let paths = scanner::expand_paths(inputs.iter().cloned());
+    if paths.is_empty() {
+        eprintln!("img montage: no images found");
+        return 1;
+    }
+    let (written, errors) = export(&paths, layout, out);
+    for error in &errors {
+        eprintln!("img montage: {}", error);
+    }
+    for path in &written {
+        println!("{}", path.display());
+    }
+    i32::from(!errors.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..5)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.png", i));
+                RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255])).save(&path).unwrap();
+                path
+            })
+            .collect();
+
+        let layout = Layout {
+            cols: 2,
+            rows: 2,
+            tile: 32,
+            spacing: 4,
+            ..Default::default()
+        };
+        let (sheets, errors) = render(&paths, &layout);
+        assert!(errors.is_empty());
+        assert_eq!(sheets.len(), 2);
+        let caption = layout.caption_height();
+        assert_eq!(sheets[0].dimensions(), (2 * 36 + 4, 2 * (36 + caption) + 4));
+        assert_eq!(sheets[1].dimensions(), (36 + 4, 36 + caption + 4));
+        // Thumbnail is 32x16, centered vertically in the tile
+        assert_eq!(sheets[0].get_pixel(4 + 16, 4 + 16).0, [255, 0, 0, 255]);
+        assert_eq!(sheets[0].get_pixel(4 + 16, 4 + 2).0, [0x20, 0x20, 0x20, 255]);
+
+        assert_eq!(sheet_paths(Path::new("out/sheet.png"), 2)[1], PathBuf::from("out/sheet-2.png"));
+        assert_eq!(Layout::default().sheet_count(20_000), 417);
+    }
+}
@@ -0,0 +1,155 @@
+use eframe::egui;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, OnceLock};
+
+/// Split a complete path list. NUL-separated if it contains any NUL byte,
+/// newline-separated otherwise.
+pub fn split_path_list(data: &[u8]) -> Vec<PathBuf> {
+    let separator = if data.contains(&0) { b'\0' } else { b'\n' };
+    data.split(|&b| b == separator)
+        .filter_map(|entry| parse_entry(entry, separator))
+        .collect()
+}
+
+fn parse_entry(entry: &[u8], separator: u8) -> Option<PathBuf> {
+    let entry = if separator == b'\n' {
+        entry.strip_suffix(b"\r").unwrap_or(entry)
+    } else {
+        entry
+    };
+    if entry.is_empty() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(PathBuf::from(std::ffi::OsStr::from_bytes(entry)))
+    }
+    #[cfg(not(unix))]
+    {
+        Some(PathBuf::from(String::from_utf8_lossy(entry).into_owned()))
+    }
+}
+
+/// Read until the first entry is complete and pick its separator the way
+/// [`split_path_list`] does, from the data that has arrived by then.
+/// Returns the separator and the bytes read.
+fn detect_separator(reader: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut start = Vec::new();
+    let mut chunk = [0; 4096];
+    while !start.contains(&b'\n') && !start.contains(&0) {
+        match reader.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => start.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let separator = if start.contains(&0) { b'\0' } else { b'\n' };
+    (separator, start)
+}
+
+/// Paths arriving incrementally from a reader (usually stdin), so the viewer
+/// can start before the list is complete.
+pub struct PathStream {
+    rx: Receiver<PathBuf>,
+    ctx: Arc<OnceLock<egui::Context>>,
+}
+
+impl PathStream {
+    /// Read paths separated by `separator`, or by NUL or newline as
+    /// detected from the start of the stream if unset.
+    pub fn spawn(mut reader: impl Read + Send + 'static, separator: Option<u8>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let ctx: Arc<OnceLock<egui::Context>> = Arc::new(OnceLock::new());
+        let ctx_clone = ctx.clone();
+
+        std::thread::spawn(move || {
+            let (separator, start) = match separator {
+                Some(separator) => (separator, Vec::new()),
+                None => detect_separator(&mut reader),
+            };
+            let mut reader = BufReader::new(std::io::Cursor::new(start).chain(reader));
+            let mut entry = Vec::new();
+            loop {
+                entry.clear();
+                match reader.read_until(separator, &mut entry) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let trimmed = entry.strip_suffix(&[separator]).unwrap_or(&entry);
+                if let Some(path) = parse_entry(trimmed, separator) {
+                    if tx.send(path).is_err() {
+                        break;
+                    }
+                    if let Some(ctx) = ctx_clone.get() {
+                        ctx.request_repaint();
+                    }
+                }
+            }
+            // Wake the viewer once more so it notices the end of the stream
+            if let Some(ctx) = ctx_clone.get() {
+                ctx.request_repaint();
+            }
+        });
+
+        Self { rx, ctx }
+    }
+
+    /// Repaint `ctx` whenever a new path arrives.
+    pub fn attach(&self, ctx: &egui::Context) {
+        let _ = self.ctx.set(ctx.clone());
+    }
+
+    /// Block until the next path arrives. `None` once the stream has ended.
+    pub fn recv(&self) -> Option<PathBuf> {
+        self.rx.recv().ok()
+    }
+
+    /// Paths received so far without blocking, plus whether the stream is
+    /// still open.
+    pub fn drain(&self) -> (Vec<PathBuf>, bool) {
+        let mut paths = Vec::new();
+        loop {
+            match self.rx.try_recv() {
+                Ok(path) => paths.push(path),
+                Err(TryRecvError::Empty) => return (paths, true),
+                Err(TryRecvError::Disconnected) => return (paths, false),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path_list() {
+        assert_eq!(
+            split_path_list(b"a.png\r\nb dir/c.jpg\n\n"),
+            vec![PathBuf::from("a.png"), PathBuf::from("b dir/c.jpg")]
+        );
+        assert_eq!(
+            split_path_list(b"line\nbreak.png\0d.gif\0"),
+            vec![PathBuf::from("line\nbreak.png"), PathBuf::from("d.gif")]
+        );
+    }
+
+    #[test]
+    fn test_stream_preserves_order() {
+        let stream = PathStream::spawn(&b"one.png\0two.png\0"[..], Some(b'\0'));
+        assert_eq!(stream.recv(), Some(PathBuf::from("one.png")));
+        assert_eq!(stream.recv(), Some(PathBuf::from("two.png")));
+        assert_eq!(stream.recv(), None);
+
+        // Detected like split_path_list when no separator is given
+        let stream = PathStream::spawn(&b"line\nbreak.png\0d.gif"[..], None);
+        assert_eq!(stream.recv(), Some(PathBuf::from("line\nbreak.png")));
+        assert_eq!(stream.recv(), Some(PathBuf::from("d.gif")));
+        assert_eq!(stream.recv(), None);
+        let stream = PathStream::spawn(&b"a.png\nb.png\n"[..], None);
+        assert_eq!(stream.recv(), Some(PathBuf::from("a.png")));
+        assert_eq!(stream.recv(), Some(PathBuf::from("b.png")));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::metadata::{self, ExifDate};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `{n}` or `{n:04}`: running counter, optionally padded
+    Counter { width: usize, zero: bool },
+    Stem,
+    Ext,
+    Width,
+    Height,
+    /// `{exif.date}` or `{exif.date:%Y%m%d}`
+    ExifDate(String),
+}
+
+/// What templates read from a file besides its name, read once when the
+/// batch is chosen rather than for every preview.
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    dimensions: Result<(u32, u32), String>,
+    date: Option<ExifDate>,
+}
+
+impl FileInfo {
+    pub fn read(path: &Path) -> Self {
+        Self {
+            dimensions: image::image_dimensions(path).map_err(|e| e.to_string()),
+            date: metadata::read_exif(path).and_then(|exif| metadata::date_taken(&exif)),
+        }
+    }
+}
+
+/// A file name template such as `{exif.date:%Y%m%d}_{n:04}.{ext}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut token = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => token.push(c),
+                            None => return Err("unclosed '{'".to_string()),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Self::parse_token(&token)?);
+                }
+                '}' => return Err("unmatched '}' (use '}}' for a literal brace)".to_string()),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    fn parse_token(token: &str) -> Result<Part, String> {
+        let (name, spec) = match token.split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (token, None),
+        };
+        match (name, spec) {
+            ("n", None) => Ok(Part::Counter { width: 0, zero: false }),
+            ("n", Some(spec)) => {
+                let width = spec.parse().map_err(|_| format!("invalid counter width '{}'", spec))?;
+                Ok(Part::Counter {
+                    width,
+                    zero: spec.starts_with('0'),
+                })
+            }
+            ("stem", None) => Ok(Part::Stem),
+            ("ext", None) => Ok(Part::Ext),
+            ("w", None) => Ok(Part::Width),
+            ("h", None) => Ok(Part::Height),
+            ("exif.date", spec) => Ok(Part::ExifDate(spec.unwrap_or("%Y%m%d").to_string())),
+            _ => Err(format!("unknown token '{{{}}}'", token)),
+        }
+    }
+
+    /// New file name for `path`, the `n`-th file of the batch.
+    pub fn render(&self, n: usize, path: &Path, info: &FileInfo) -> Result<String, String> {
+        let mut out = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Counter { width, zero: true } => out.push_str(&format!("{:0width$}", n, width = *width)),
+                Part::Counter { width, zero: false } => out.push_str(&format!("{:width$}", n, width = *width)),
+                Part::Stem => out.push_str(&path.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Ext => out.push_str(&path.extension().unwrap_or_default().to_string_lossy()),
+                Part::Width | Part::Height => {
+                    let (w, h) = info.dimensions.clone()?;
+                    out.push_str(&if *part == Part::Width { w } else { h }.to_string());
+                }
+                Part::ExifDate(pattern) => out.push_str(&info.date.ok_or("no EXIF date")?.format(pattern)),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// One planned rename, with the reason it can't be done if there is one.
+#[derive(Clone, Debug)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub problem: Option<String>,
+}
+
+/// Work out the new names for `paths`, with `info` read for each of them,
+/// without touching anything, flagging invalid names and collisions with
+/// each other or with existing files.
+pub fn plan(paths: &[PathBuf], info: &[FileInfo], template: &Template, start: usize) -> Vec<Rename> {
+    let sources: HashSet<&PathBuf> = paths.iter().collect();
+    let mut targets = HashSet::new();
+
+    paths
+        .iter()
+        .zip(info)
+        .enumerate()
+        .map(|(i, (from, info))| {
+            let (to, mut problem) = match template.render(start + i, from, info) {
+                Ok(name) => (from.with_file_name(&name), check_name(&name).err()),
+                Err(e) => (from.clone(), Some(e)),
+            };
+            if problem.is_none() {
+                if !targets.insert(to.clone()) {
+                    problem = Some("same name as another file in this batch".to_string());
+                } else if to != *from && to.exists() && !sources.contains(&to) {
+                    problem = Some(format!("{} already exists", to.display()));
+                }
+            }
+            Rename {
+                from: from.clone(),
+                to,
+                problem,
+            }
+        })
+        .collect()
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." {
+        Err("empty file name".to_string())
+    } else if name.contains('/') || name.contains('\0') {
+        Err("file name contains '/'".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Carry out a plan that has no problems. Files go through a temporary
+/// name first so that names can be swapped within the batch. Returns the
+/// renames that were done along with a message for each one that failed.
+pub fn apply(renames: &[Rename]) -> (Vec<(PathBuf, PathBuf)>, Vec<String>) {
+    if let Some(rename) = renames.iter().find(|r| r.problem.is_some()) {
+        let problem = rename.problem.as_deref().unwrap_or_default();
+        return (Vec::new(), vec![format!("{}: {}", rename.from.display(), problem)]);
+    }
+    let pending: Vec<&Rename> = renames.iter().filter(|r| r.from != r.to).collect();
+
+    let mut staged: Vec<(&Rename, PathBuf)> = Vec::new();
+    for (i, rename) in pending.iter().enumerate() {
+        let temp = rename
+            .from
+            .with_file_name(format!(".img-rename-{}-{}", std::process::id(), i));
+        if let Err(e) = std::fs::rename(&rename.from, &temp) {
+            // Put back what was already moved aside
+            for (rename, temp) in staged {
+                let _ = std::fs::rename(&temp, &rename.from);
+            }
+            return (Vec::new(), vec![format!("{}: {}", rename.from.display(), e)]);
+        }
+        staged.push((*rename, temp));
+    }
+
+    let mut done = Vec::new();
+    let mut errors = Vec::new();
+    for (rename, temp) in staged {
+        match std::fs::rename(&temp, &rename.to) {
+            Ok(()) => done.push((rename.from.clone(), rename.to.clone())),
+            Err(e) => {
+                let _ = std::fs::rename(&temp, &rename.from);
+                errors.push(format!("{}: {}", rename.to.display(), e));
+            }
+        }
+    }
+    (done, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_render() {
+        let template = Template::parse("{n:04}_{stem}.{ext}").unwrap();
+        assert_eq!(template.render(7, Path::new("/x/cat.png"), &FileInfo::read(Path::new("/x/cat.png"))).unwrap(), "0007_cat.png");
+
+        let template = Template::parse("{{{n}}}").unwrap();
+        assert_eq!(template.render(3, Path::new("a.jpg"), &FileInfo::read(Path::new("a.jpg"))).unwrap(), "{3}");
+
+        assert!(Template::parse("{bogus}").is_err());
+        assert!(Template::parse("a}b").is_err());
+        assert_eq!(Template::parse("{stem").err(), Some("unclosed '{'".to_string()));
+        assert!(Template::parse("{exif.date}").unwrap().render(1, Path::new("missing.jpg"), &FileInfo::read(Path::new("missing.jpg"))).is_err());
+    }
+
+    #[test]
+    fn test_plan_detects_collisions_and_apply_swaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = dir.path().join("1.png");
+        let two = dir.path().join("2.png");
+        std::fs::write(&one, b"one").unwrap();
+        std::fs::write(&two, b"two").unwrap();
+        std::fs::write(dir.path().join("taken.png"), b"").unwrap();
+
+        let info = |paths: &[PathBuf]| paths.iter().map(|p| FileInfo::read(p)).collect::<Vec<_>>();
+        let same = plan(&[one.clone(), two.clone()], &info(&[one.clone(), two.clone()]), &Template::parse("same.png").unwrap(), 1);
+        assert!(same[0].problem.is_none());
+        assert!(same[1].problem.is_some());
+
+        let taken = plan(std::slice::from_ref(&one), &info(std::slice::from_ref(&one)), &Template::parse("taken.png").unwrap(), 1);
+        assert!(taken[0].problem.is_some());
+        assert!(!apply(&taken).1.is_empty());
+
+        // 2.png -> 1.png and 1.png -> 2.png
+        let swap = plan(&[two.clone(), one.clone()], &info(&[two.clone(), one.clone()]), &Template::parse("{n}.png").unwrap(), 1);
+        assert!(swap.iter().all(|r| r.problem.is_none()));
+        assert_eq!(apply(&swap).0.len(), 2);
+        assert_eq!(std::fs::read(&one).unwrap(), b"two");
+        assert_eq!(std::fs::read(&two).unwrap(), b"one");
+    }
+}
@@ -0,0 +1,127 @@
+use eframe::egui;
+use image::imageops::FilterType;
+
+/// Longest side, in pixels, of images that count as sprites or pixel art
+/// for the automatic filter.
+const SMALL_IMAGE: u32 = 512;
+
+/// Screen size of a pixel, in points, from which the pixel grid is drawn.
+const GRID_MIN_PITCH: f32 = 8.0;
+
+/// How the image is resampled for display.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilterMode {
+    /// Nearest for integer upscales of small images, smooth otherwise
+    #[default]
+    Auto,
+    Nearest,
+    Linear,
+    Lanczos,
+}
+
+impl FilterMode {
+    pub fn next(self) -> Self {
+        match self {
+            FilterMode::Auto => FilterMode::Nearest,
+            FilterMode::Nearest => FilterMode::Linear,
+            FilterMode::Linear => FilterMode::Lanczos,
+            FilterMode::Lanczos => FilterMode::Auto,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FilterMode::Auto => "auto",
+            FilterMode::Nearest => "nearest",
+            FilterMode::Linear => "linear",
+            FilterMode::Lanczos => "Lanczos",
+        }
+    }
+
+    /// Filter for shrinking a decoded image to its display copy.
+    pub fn resize_filter(self) -> FilterType {
+        match self {
+            FilterMode::Nearest => FilterType::Nearest,
+            FilterMode::Linear => FilterType::Triangle,
+            FilterMode::Auto | FilterMode::Lanczos => FilterType::Lanczos3,
+        }
+    }
+
+    /// Texture sampling when an image of `size` pixels is drawn at `scale`
+    /// screen points per pixel.
+    pub fn texture_options(self, scale: f32, size: (u32, u32)) -> egui::TextureOptions {
+        let nearest = match self {
+            FilterMode::Nearest => true,
+            FilterMode::Linear | FilterMode::Lanczos => false,
+            FilterMode::Auto => {
+                size.0.max(size.1) <= SMALL_IMAGE && scale >= 2.0 && (scale - scale.round()).abs() < 0.01
+            }
+        };
+        if nearest {
+            egui::TextureOptions::NEAREST
+        } else {
+            egui::TextureOptions::LINEAR
+        }
+    }
+}
+
+/// Round an upscale down to a whole number; downscales are left alone.
+pub fn integer_scale(scale: f32) -> f32 {
+    if scale >= 1.0 { scale.floor() } else { scale }
+}
+
+/// Next whole-number zoom step from `scale` when zooming in or out.
+pub fn integer_step(scale: f32, zoom_in: bool) -> f32 {
+    if zoom_in {
+        scale.floor() + 1.0
+    } else {
+        (scale.ceil() - 1.0).max(1.0)
+    }
+}
+
+/// Draw lines between pixels when `rect` shows an image of `pixels` at a
+/// zoom where individual pixels are big enough to tell apart.
+pub fn paint_pixel_grid(painter: &egui::Painter, rect: egui::Rect, pixels: (u32, u32)) {
+    let pitch = egui::vec2(rect.width() / pixels.0 as f32, rect.height() / pixels.1 as f32);
+    if pitch.x < GRID_MIN_PITCH || pitch.y < GRID_MIN_PITCH {
+        return;
+    }
+    let visible = rect.intersect(painter.clip_rect());
+    if !visible.is_positive() {
+        return;
+    }
+    let stroke = egui::Stroke::new(1.0, egui::Color32::from_black_alpha(110));
+
+    let first_col = ((visible.left() - rect.left()) / pitch.x).ceil() as u32;
+    let last_col = ((visible.right() - rect.left()) / pitch.x).floor() as u32;
+    for col in first_col..=last_col.min(pixels.0) {
+        let x = rect.left() + col as f32 * pitch.x;
+        painter.line_segment([egui::pos2(x, visible.top()), egui::pos2(x, visible.bottom())], stroke);
+    }
+    let first_row = ((visible.top() - rect.top()) / pitch.y).ceil() as u32;
+    let last_row = ((visible.bottom() - rect.top()) / pitch.y).floor() as u32;
+    for row in first_row..=last_row.min(pixels.1) {
+        let y = rect.top() + row as f32 * pitch.y;
+        painter.line_segment([egui::pos2(visible.left(), y), egui::pos2(visible.right(), y)], stroke);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_filter_and_integer_scaling() {
+        let sprite = (32, 32);
+        assert_eq!(FilterMode::Auto.texture_options(4.0, sprite), egui::TextureOptions::NEAREST);
+        assert_eq!(FilterMode::Auto.texture_options(3.5, sprite), egui::TextureOptions::LINEAR);
+        assert_eq!(FilterMode::Auto.texture_options(4.0, (4000, 3000)), egui::TextureOptions::LINEAR);
+        assert_eq!(FilterMode::Nearest.texture_options(0.5, sprite), egui::TextureOptions::NEAREST);
+
+        assert_eq!(integer_scale(3.7), 3.0);
+        assert_eq!(integer_scale(0.4), 0.4);
+        assert_eq!(integer_step(2.5, true), 3.0);
+        assert_eq!(integer_step(2.5, false), 2.0);
+        assert_eq!(integer_step(1.0, false), 1.0);
+    }
+}
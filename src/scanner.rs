@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Extensions picked up when scanning directories.
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "bmp"];
+
+/// Finds images in directories.
+#[derive(Clone, Debug)]
+pub struct Scanner {
+    /// Extensions without the dot, matched case-insensitively
+    pub extensions: Vec<String>,
+    /// Descend into subdirectories
+    pub recursive: bool,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self {
+            extensions: IMAGE_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            recursive: true,
+        }
+    }
+}
+
+impl Scanner {
+    pub fn is_image(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+
+    /// Images under `dir`, in the order the directory walk finds them.
+    pub fn scan(&self, dir: &Path) -> Vec<PathBuf> {
+        let _span = tracing::debug_span!("scan", dir = %dir.display(), recursive = self.recursive).entered();
+        let walk = WalkDir::new(dir);
+        let walk = if self.recursive { walk } else { walk.max_depth(1) };
+        let images: Vec<PathBuf> = walk
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .filter(|p| self.is_image(p))
+            .collect();
+        tracing::debug!(found = images.len(), "scanned");
+        images
+    }
+
+    /// Expand a list of files and directories, keeping the given order.
+    /// Directories are replaced by the images found inside them.
+    pub fn expand(&self, paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+        let mut images = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                images.extend(self.scan(&path));
+            } else {
+                images.push(path);
+            }
+        }
+        images
+    }
+}
+
+/// [`Scanner::expand`] with the default extensions, recursing into
+/// subdirectories.
+pub fn expand_paths(paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    Scanner::default().expand(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_and_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub")).unwrap();
+        for name in ["a.PNG", "notes.txt", "sub/b.jpg"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let mut found = Scanner::default().scan(dir.path());
+        found.sort();
+        assert_eq!(found, vec![dir.path().join("a.PNG"), dir.path().join("sub").join("b.jpg")]);
+
+        let flat = Scanner { recursive: false, ..Default::default() };
+        assert_eq!(flat.scan(dir.path()), vec![dir.path().join("a.PNG")]);
+
+        let expanded = flat.expand([PathBuf::from("z.gif"), dir.path().to_path_buf()]);
+        assert_eq!(expanded, vec![PathBuf::from("z.gif"), dir.path().join("a.PNG")]);
+    }
+}
@@ -0,0 +1,238 @@
+use eframe::egui;
+use std::path::PathBuf;
+
+/// Results listed under the search prompt.
+const SHOWN_RESULTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PromptKind {
+    /// `/`, fuzzy search over the paths
+    Search,
+    /// `:`, go to an image by its number
+    Jump,
+}
+
+/// A path that matched a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    /// Position in the image list
+    pub index: usize,
+    pub score: i64,
+    /// Characters of the path that matched, for highlighting
+    pub positions: Vec<usize>,
+}
+
+/// The `/` and `:` prompt. It's typed into through key events rather than
+/// a text field, so it works the same in the terminal.
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+    /// Where the list was when the prompt opened, to go back to on Esc
+    pub origin: usize,
+    /// Search results for `input`, best first
+    pub matches: Vec<Match>,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind, origin: usize) -> Self {
+        Self {
+            kind,
+            input: String::new(),
+            origin,
+            matches: Vec::new(),
+        }
+    }
+
+    /// Add typed text. The jump prompt only takes digits.
+    pub fn type_text(&mut self, text: &str) {
+        match self.kind {
+            PromptKind::Search => self.input.extend(text.chars().filter(|c| !c.is_control())),
+            PromptKind::Jump => self.input.extend(text.chars().filter(char::is_ascii_digit)),
+        }
+    }
+
+    pub fn refresh(&mut self, paths: &[PathBuf]) {
+        if self.kind == PromptKind::Search {
+            self.matches = rank(&self.input, paths);
+        }
+    }
+
+    /// The list index the input points at: the best match, or the image
+    /// with that number.
+    pub fn target(&self) -> Option<usize> {
+        match self.kind {
+            PromptKind::Search => self.matches.first().map(|m| m.index),
+            PromptKind::Jump => self.input.parse::<usize>().ok()?.checked_sub(1),
+        }
+    }
+
+    /// The prompt line, as the terminal shows it.
+    pub fn line(&self) -> String {
+        match self.kind {
+            PromptKind::Search if self.input.is_empty() => "/".to_string(),
+            PromptKind::Search => {
+                let count = self.matches.len();
+                format!("/{}  {} match{}", self.input, count, if count == 1 { "" } else { "es" })
+            }
+            PromptKind::Jump => format!(":{}", self.input),
+        }
+    }
+
+    /// The prompt bar with the best matches highlighted. Returns the list
+    /// index of a result that was clicked.
+    pub fn show(&self, ctx: &egui::Context, paths: &[PathBuf], current: usize) -> Option<usize> {
+        let mut clicked = None;
+        egui::TopBottomPanel::bottom("prompt").show(ctx, |ui| {
+            if self.kind == PromptKind::Search {
+                for m in self.matches.iter().take(SHOWN_RESULTS) {
+                    let text = highlighted(ui, &paths[m.index].to_string_lossy(), &m.positions);
+                    if ui.selectable_label(m.index == current, text).clicked() {
+                        clicked = Some(m.index);
+                    }
+                }
+                if self.matches.len() > SHOWN_RESULTS {
+                    ui.weak(format!("and {} more", self.matches.len() - SHOWN_RESULTS));
+                }
+            }
+            ui.monospace(self.line());
+        });
+        clicked
+    }
+}
+
+/// `text` with the characters at `positions` picked out.
+fn highlighted(ui: &egui::Ui, text: &str, positions: &[usize]) -> egui::text::LayoutJob {
+    let font_id = egui::TextStyle::Body.resolve(ui.style());
+    let plain = egui::TextFormat {
+        font_id: font_id.clone(),
+        color: ui.visuals().text_color(),
+        ..Default::default()
+    };
+    let strong = egui::TextFormat {
+        font_id,
+        color: ui.visuals().strong_text_color(),
+        underline: egui::Stroke::new(1.0, ui.visuals().strong_text_color()),
+        ..Default::default()
+    };
+    let mut job = egui::text::LayoutJob::default();
+    let mut buf = [0; 4];
+    for (i, c) in text.chars().enumerate() {
+        let format = if positions.binary_search(&i).is_ok() { &strong } else { &plain };
+        job.append(c.encode_utf8(&mut buf), 0.0, format.clone());
+    }
+    job
+}
+
+/// Paths matching `query`, best first and in list order among equals.
+pub fn rank(query: &str, paths: &[PathBuf]) -> Vec<Match> {
+    let mut matches: Vec<Match> = paths
+        .iter()
+        .enumerate()
+        .filter_map(|(index, path)| {
+            let (score, positions) = fuzzy_match(query, &path.to_string_lossy())?;
+            Some(Match { index, score, positions })
+        })
+        .collect();
+    matches.sort_by_key(|m| (std::cmp::Reverse(m.score), m.index));
+    matches
+}
+
+/// List indices of the paths matching `query`, in list order.
+pub fn matching_indices(query: &str, paths: &[PathBuf]) -> Vec<usize> {
+    let mut indices: Vec<usize> = rank(query, paths).into_iter().map(|m| m.index).collect();
+    indices.sort_unstable();
+    indices
+}
+
+/// Score `query` as a subsequence of `candidate`, with the characters it
+/// matched. Matches in the file name, runs of consecutive characters and
+/// the starts of words score higher. Case only matters when the query has
+/// capitals in it.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    if query.is_empty() {
+        return None;
+    }
+    let chars: Vec<char> = candidate.chars().collect();
+    let folded: Vec<char> = chars.iter().map(|&c| fold(c)).collect();
+    let name_start = chars.iter().rposition(|&c| c == '/' || c == '\\').map_or(0, |i| i + 1);
+    let positions = locate(&query, &folded, name_start).or_else(|| locate(&query, &folded, 0))?;
+
+    let mut score = 0;
+    for (n, &p) in positions.iter().enumerate() {
+        score += 16;
+        if n > 0 {
+            let gap = p - positions[n - 1] - 1;
+            score += if gap == 0 { 24 } else { -(gap.min(16) as i64) };
+        }
+        if p == 0 || is_word_start(chars[p - 1], chars[p]) {
+            score += 20;
+        }
+        if p >= name_start {
+            score += 8;
+        }
+    }
+    // Among equal matches the shorter name is the likelier one
+    score -= (chars.len() - name_start) as i64 / 8;
+    Some((score, positions))
+}
+
+/// Where `query` matches in `chars` from `start`: the first place it can
+/// end, then back from there to the latest start, for the tightest match.
+fn locate(query: &[char], chars: &[char], start: usize) -> Option<Vec<usize>> {
+    let mut matched = 0;
+    let end = (start..chars.len()).find(|&i| {
+        if chars[i] == query[matched] {
+            matched += 1;
+        }
+        matched == query.len()
+    })?;
+    let mut positions = Vec::with_capacity(query.len());
+    for i in (start..=end).rev() {
+        if positions.len() < query.len() && chars[i] == query[query.len() - 1 - positions.len()] {
+            positions.push(i);
+        }
+    }
+    positions.reverse();
+    Some(positions)
+}
+
+fn is_word_start(prev: char, c: char) -> bool {
+    matches!(prev, '/' | '\\' | '_' | '-' | '.' | ' ')
+        || (prev.is_lowercase() && c.is_uppercase())
+        || (!prev.is_ascii_digit() && c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match_and_rank() {
+        assert_eq!(fuzzy_match("ct", "shots/cat.png").map(|(_, p)| p), Some(vec![6, 8]));
+        assert_eq!(fuzzy_match("sc", "shots/cat.png").map(|(_, p)| p), Some(vec![4, 6]));
+        assert_eq!(fuzzy_match("dog", "shots/cat.png"), None);
+        assert_eq!(fuzzy_match("", "shots/cat.png"), None);
+        // Capitals make the search case-sensitive
+        assert!(fuzzy_match("cat", "shots/CAT.png").is_some());
+        assert!(fuzzy_match("Cat", "shots/cat.png").is_none());
+
+        let paths: Vec<PathBuf> = ["cat/bird.png", "b/concat.png", "b/cat.png", "b/cat_2.png"].iter().map(PathBuf::from).collect();
+        let ranked: Vec<usize> = rank("cat", &paths).iter().map(|m| m.index).collect();
+        assert_eq!(ranked, [2, 3, 1, 0]);
+        assert_eq!(matching_indices("cat", &paths), [0, 1, 2, 3]);
+
+        let mut prompt = Prompt::new(PromptKind::Jump, 0);
+        prompt.type_text("1x2");
+        assert_eq!((prompt.line(), prompt.target()), (":12".to_string(), Some(11)));
+        prompt.input = "0".to_string();
+        assert_eq!(prompt.target(), None);
+
+        let mut prompt = Prompt::new(PromptKind::Search, 0);
+        prompt.type_text("bird");
+        prompt.refresh(&paths);
+        assert_eq!((prompt.line(), prompt.target()), ("/bird  1 match".to_string(), Some(0)));
+    }
+}
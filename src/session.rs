@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Folders kept in the recent list.
+const MAX_RECENT: usize = 20;
+
+/// `$XDG_STATE_HOME/img`, falling back to `~/.local/state/img`.
+pub fn state_dir() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state.join("img"))
+}
+
+/// Order of the image list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// As given on the command line or found by the scan
+    #[default]
+    Given,
+    Name,
+    /// Oldest first
+    Modified,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Given => SortOrder::Name,
+            SortOrder::Name => SortOrder::Modified,
+            SortOrder::Modified => SortOrder::Given,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Given => "as given",
+            SortOrder::Name => "name",
+            SortOrder::Modified => "date modified",
+        }
+    }
+
+    /// Sort `paths`, using `given` (each path's original position) to put
+    /// them back in the order they came in.
+    pub fn sort(self, paths: &mut [PathBuf], given: &HashMap<PathBuf, usize>) {
+        match self {
+            SortOrder::Given => paths.sort_by_key(|p| given.get(p).copied().unwrap_or(usize::MAX)),
+            SortOrder::Name => paths.sort(),
+            SortOrder::Modified => {
+                paths.sort_by_cached_key(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            }
+        }
+    }
+}
+
+/// What's remembered about a directory. Paths are relative to it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// The directory, to tell apart the rare ones whose file names collide
+    pub dir: PathBuf,
+    /// Last image shown
+    pub current: Option<PathBuf>,
+    /// Clockwise degrees, for images that aren't upright
+    pub rotations: BTreeMap<PathBuf, u32>,
+    /// Fixed zoom factor, or fit to the window when unset
+    pub zoom: Option<f32>,
+    pub sort: SortOrder,
+    pub marked: BTreeSet<PathBuf>,
+}
+
+/// Sessions and the recent folder list, kept under the state directory.
+pub struct SessionStore {
+    root: PathBuf,
+}
+
+impl SessionStore {
+    /// The store in [`state_dir`].
+    pub fn open() -> Option<Self> {
+        Some(Self::at(state_dir()?))
+    }
+
+    pub fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn session_file(&self, dir: &Path) -> PathBuf {
+        // FNV-1a, which unlike the std hasher is stable across releases
+        let hash = dir
+            .as_os_str()
+            .as_encoded_bytes()
+            .iter()
+            .fold(0xcbf29ce484222325u64, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3));
+        self.root.join("sessions").join(format!("{:016x}.json", hash))
+    }
+
+    /// The saved state of `dir`, if there is any.
+    pub fn load(&self, dir: &Path) -> Option<Session> {
+        let dir = dir.canonicalize().ok()?;
+        let text = std::fs::read_to_string(self.session_file(&dir)).ok()?;
+        let session: Session = serde_json::from_str(&text).ok()?;
+        (session.dir == dir).then_some(session)
+    }
+
+    pub fn save(&self, dir: &Path, session: &Session) -> std::io::Result<()> {
+        let dir = dir.canonicalize()?;
+        let session = Session { dir: dir.clone(), ..session.clone() };
+        write_json(&self.session_file(&dir), &session)
+    }
+
+    /// Folders opened lately, most recent first.
+    pub fn recent(&self) -> Vec<PathBuf> {
+        std::fs::read_to_string(self.root.join("recent.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Move `dir` to the top of the recent list.
+    pub fn add_recent(&self, dir: &Path) -> std::io::Result<()> {
+        let dir = dir.canonicalize()?;
+        let mut recent = self.recent();
+        recent.retain(|d| *d != dir);
+        recent.insert(0, dir);
+        recent.truncate(MAX_RECENT);
+        write_json(&self.root.join("recent.json"), &recent)
+    }
+}
+
+/// Write through a temporary file so a crash never leaves half a file.
+fn write_json(path: &Path, value: &impl Serialize) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_and_recent() {
+        let root = tempfile::tempdir().unwrap();
+        let (a, b) = (root.path().join("a"), root.path().join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        let store = SessionStore::at(root.path().join("state"));
+
+        assert_eq!(store.load(&a), None);
+        let session = Session {
+            current: Some(PathBuf::from("x.png")),
+            rotations: BTreeMap::from([(PathBuf::from("x.png"), 90)]),
+            zoom: Some(2.0),
+            sort: SortOrder::Modified,
+            marked: BTreeSet::from([PathBuf::from("y.png")]),
+            ..Default::default()
+        };
+        store.save(&a.join("."), &session).unwrap();
+        let loaded = store.load(&a).unwrap();
+        assert_eq!((loaded.current, loaded.zoom, loaded.sort), (session.current, Some(2.0), SortOrder::Modified));
+        assert_eq!((loaded.rotations, loaded.marked), (session.rotations, session.marked));
+        assert_eq!(store.load(&b), None);
+
+        store.add_recent(&a).unwrap();
+        store.add_recent(&b).unwrap();
+        store.add_recent(&a).unwrap();
+        assert_eq!(store.recent(), vec![a.canonicalize().unwrap(), b.canonicalize().unwrap()]);
+
+        let given = HashMap::from([(PathBuf::from("b"), 0), (PathBuf::from("a"), 1)]);
+        let mut paths = vec![PathBuf::from("a"), PathBuf::from("b")];
+        SortOrder::Given.sort(&mut paths, &given);
+        assert_eq!(paths, [PathBuf::from("b"), PathBuf::from("a")]);
+        SortOrder::Name.sort(&mut paths, &given);
+        assert_eq!(paths, [PathBuf::from("a"), PathBuf::from("b")]);
+    }
+}
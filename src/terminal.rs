@@ -0,0 +1,514 @@
+use crate::{ImageViewer, ViewerOptions, histogram, keyhandler, pathlist, rotate_image};
+use base64::Engine;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{QueueableCommand, cursor, style, terminal};
+use eframe::egui;
+use image::{DynamicImage, GenericImageView, RgbImage};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Cell size assumed when the terminal doesn't report its pixel size.
+const FALLBACK_CELL: (u32, u32) = (8, 16);
+
+/// How images are drawn in the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    ITerm,
+    Sixel,
+    /// Half-block characters in truecolor, works almost anywhere
+    Blocks,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 4] = [Protocol::Kitty, Protocol::ITerm, Protocol::Sixel, Protocol::Blocks];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Kitty => "kitty",
+            Protocol::ITerm => "iterm",
+            Protocol::Sixel => "sixel",
+            Protocol::Blocks => "blocks",
+        }
+    }
+
+    /// Best guess from the environment. `TERM` and `LC_TERMINAL` are the
+    /// ones that usually make it through SSH.
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        if matches!(term.as_str(), "xterm-kitty" | "xterm-ghostty") || !var("KITTY_WINDOW_ID").is_empty() {
+            Protocol::Kitty
+        } else if var("LC_TERMINAL") == "iTerm2" || matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") {
+            Protocol::ITerm
+        } else if ["foot", "mlterm", "yaft", "sixel"].iter().any(|t| term.contains(t)) {
+            Protocol::Sixel
+        } else {
+            Protocol::Blocks
+        }
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| format!("unknown terminal protocol '{}' (kitty, iterm, sixel, blocks)", s))
+    }
+}
+
+/// Whether a window can be opened at all. Without X11 or Wayland there is
+/// nowhere to put one.
+pub fn has_display() -> bool {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        let set = |name| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+        set("DISPLAY") || set("WAYLAND_DISPLAY")
+    } else {
+        true
+    }
+}
+
+/// The controlling terminal, so images still land on screen when stdout
+/// is piped.
+fn open_tty() -> Box<dyn Write> {
+    match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(tty) => Box::new(std::io::BufWriter::new(tty)),
+        Err(_) => Box::new(std::io::stdout()),
+    }
+}
+
+/// The same key as egui would have reported it, so the window's bindings
+/// apply unchanged.
+fn egui_key(event: &KeyEvent) -> Option<(egui::Key, egui::Modifiers)> {
+    let key = match event.code {
+        KeyCode::Char(c) => egui::Key::from_name(&c.to_string())?,
+        KeyCode::F(n) => egui::Key::from_name(&format!("F{}", n))?,
+        KeyCode::Esc => egui::Key::Escape,
+        KeyCode::Enter => egui::Key::Enter,
+        KeyCode::Tab => egui::Key::Tab,
+        KeyCode::Backspace => egui::Key::Backspace,
+        KeyCode::Delete => egui::Key::Delete,
+        KeyCode::Insert => egui::Key::Insert,
+        KeyCode::Left => egui::Key::ArrowLeft,
+        KeyCode::Right => egui::Key::ArrowRight,
+        KeyCode::Up => egui::Key::ArrowUp,
+        KeyCode::Down => egui::Key::ArrowDown,
+        KeyCode::Home => egui::Key::Home,
+        KeyCode::End => egui::Key::End,
+        KeyCode::PageUp => egui::Key::PageUp,
+        KeyCode::PageDown => egui::Key::PageDown,
+        _ => return None,
+    };
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let modifiers = egui::Modifiers {
+        alt: event.modifiers.contains(KeyModifiers::ALT),
+        ctrl,
+        shift: event.modifiers.contains(KeyModifiers::SHIFT) || matches!(event.code, KeyCode::Char(c) if c.is_uppercase()),
+        mac_cmd: false,
+        command: ctrl,
+    };
+    Some((key, modifiers))
+}
+
+/// The text a key types, as egui reports it alongside the key. Some
+/// characters, like `/`, have no egui key and only come through this way.
+fn typed_text(event: &KeyEvent) -> Option<String> {
+    match event.code {
+        KeyCode::Char(c) if !event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => Some(c.to_string()),
+        _ => None,
+    }
+}
+
+/// Largest size with the aspect ratio of `size` that fits in `bounds`.
+fn fit(size: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let scale = (bounds.0 as f32 / size.0.max(1) as f32).min(bounds.1 as f32 / size.1.max(1) as f32);
+    (((size.0 as f32 * scale) as u32).max(1), ((size.1 as f32 * scale) as u32).max(1))
+}
+
+/// The current image as the window would show it.
+fn current_frame(viewer: &ImageViewer) -> Option<DynamicImage> {
+    let image = &viewer.current_image.as_ref()?.display_image;
+    let adjustments = viewer.current_adjustments();
+    let image = if adjustments.is_identity() && viewer.channel == histogram::Channel::All {
+        image.clone()
+    } else {
+        histogram::extract_channel(&adjustments.apply(image), viewer.channel)
+    };
+    Some(rotate_image(&image, viewer.current_rotation()))
+}
+
+fn encode_png(image: &DynamicImage) -> Vec<u8> {
+    let mut png = Vec::new();
+    let _ = image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png);
+    png
+}
+
+fn draw_kitty(out: &mut dyn Write, image: &DynamicImage) -> std::io::Result<()> {
+    let data = base64::engine::general_purpose::STANDARD.encode(encode_png(image));
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            write!(out, "\x1b_Ga=T,f=100,q=2,m={};", more)?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        out.write_all(b"\x1b\\")?;
+    }
+    Ok(())
+}
+
+fn draw_iterm(out: &mut dyn Write, image: &DynamicImage) -> std::io::Result<()> {
+    let png = encode_png(image);
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={}px;height={}px;preserveAspectRatio=1:{}\x07",
+        png.len(),
+        image.width(),
+        image.height(),
+        base64::engine::general_purpose::STANDARD.encode(&png)
+    )
+}
+
+/// Sixel data for `image`, reduced to a 256-color palette.
+fn encode_sixel(image: &RgbImage) -> Vec<u8> {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    let rgba: Vec<u8> = image.pixels().flat_map(|p| [p.0[0], p.0[1], p.0[2], 255]).collect();
+    let quant = color_quant::NeuQuant::new(10, 256, &rgba);
+    let indices: Vec<u8> = rgba.chunks(4).map(|p| quant.index_of(p) as u8).collect();
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", w, h).into_bytes();
+    for (i, c) in quant.color_map_rgb().chunks(3).enumerate() {
+        let percent = |v: u8| v as u32 * 100 / 255;
+        out.extend(format!("#{};2;{};{};{}", i, percent(c[0]), percent(c[1]), percent(c[2])).bytes());
+    }
+
+    // Each band is six pixel rows; every color used in it gets one pass
+    let mut band_colors: Vec<Option<Vec<u8>>> = vec![None; 256];
+    for top in (0..h).step_by(6) {
+        for dy in 0..(h - top).min(6) {
+            for x in 0..w {
+                let color = indices[(top + dy) * w + x] as usize;
+                band_colors[color].get_or_insert_with(|| vec![0; w])[x] |= 1 << dy;
+            }
+        }
+        let mut first = true;
+        for (color, bits) in band_colors.iter_mut().enumerate() {
+            let Some(bits) = bits.take() else { continue };
+            if !first {
+                out.push(b'$');
+            }
+            first = false;
+            out.extend(format!("#{}", color).bytes());
+            for run in bits.chunk_by(|a, b| a == b) {
+                let sixel = 63 + run[0];
+                if run.len() > 3 {
+                    out.extend(format!("!{}", run.len()).bytes());
+                    out.push(sixel);
+                } else {
+                    out.extend(std::iter::repeat_n(sixel, run.len()));
+                }
+            }
+        }
+        out.push(b'-');
+    }
+    out.extend(b"\x1b\\");
+    out
+}
+
+/// Two pixels per cell: the upper one as the foreground of `▀`, the lower
+/// one as its background.
+fn draw_blocks(out: &mut dyn Write, image: &RgbImage, origin: (u16, u16)) -> std::io::Result<()> {
+    for row in 0..image.height().div_ceil(2) {
+        out.queue(cursor::MoveTo(origin.0, origin.1 + row as u16))?;
+        let mut last = None;
+        for x in 0..image.width() {
+            let top = image.get_pixel(x, row * 2).0;
+            let bottom = (row * 2 + 1 < image.height()).then(|| image.get_pixel(x, row * 2 + 1).0);
+            if last != Some((top, bottom)) {
+                write!(out, "\x1b[38;2;{};{};{}m", top[0], top[1], top[2])?;
+                match bottom {
+                    Some(b) => write!(out, "\x1b[48;2;{};{};{}m", b[0], b[1], b[2])?,
+                    None => write!(out, "\x1b[49m")?,
+                }
+                last = Some((top, bottom));
+            }
+            write!(out, "▀")?;
+        }
+        write!(out, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+/// Draw `image` centered in the top `rows` rows of the terminal.
+fn draw_image(out: &mut dyn Write, viewer: &ImageViewer, image: &DynamicImage, protocol: Protocol, (cols, rows): (u16, u16)) -> std::io::Result<()> {
+    let filter = viewer.filter.resize_filter();
+    let flatten = |image: &DynamicImage| viewer.background.flatten(&image.to_rgba8(), &viewer.options.background);
+
+    if protocol == Protocol::Blocks {
+        let (w, h) = fit(image.dimensions(), (cols as u32, rows as u32 * 2));
+        let pixels = flatten(&image.resize_exact(w, h, filter));
+        let origin = ((cols as u32 - w) / 2, (rows as u32 * 2 - h) / 4);
+        return draw_blocks(out, &pixels, (origin.0 as u16, origin.1 as u16));
+    }
+
+    let cell = match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            ((size.width / size.columns) as u32, (size.height / size.rows) as u32)
+        }
+        _ => FALLBACK_CELL,
+    };
+    // Shrink to the free area but never enlarge past the display copy
+    let (w, h) = fit(image.dimensions(), (cols as u32 * cell.0, rows as u32 * cell.1));
+    let image = if w < image.width() { image.resize_exact(w, h, filter) } else { image.clone() };
+    let used = (image.width().div_ceil(cell.0), image.height().div_ceil(cell.1));
+    let origin = ((cols as u32).saturating_sub(used.0) / 2, (rows as u32).saturating_sub(used.1) / 2);
+    out.queue(cursor::MoveTo(origin.0 as u16, origin.1 as u16))?;
+
+    match protocol {
+        Protocol::Kitty => draw_kitty(out, &image),
+        Protocol::ITerm => draw_iterm(out, &image),
+        Protocol::Sixel => out.write_all(&encode_sixel(&flatten(&image))),
+        Protocol::Blocks => unreachable!(),
+    }
+}
+
+/// What the bottom line says: position, name and size, or whatever the
+/// viewer is waiting on.
+fn status_line(viewer: &ImageViewer) -> String {
+    if viewer.show_delete_confirm
+        && let Some(path) = &viewer.image_to_delete
+    {
+        return format!("Delete {}? This cannot be undone. (y/N)", path.display());
+    }
+    if let Some(job) = &viewer.key_handler_job {
+        return format!("Running key handler {}...", job.key);
+    }
+    if viewer.key_handler_prefix {
+        return "Ctrl+X: waiting for key (Esc cancels)".to_string();
+    }
+    if let Some(prompt) = &viewer.prompt {
+        return prompt.line();
+    }
+
+    let Some(path) = viewer.images.get(viewer.current_index) else {
+        return if viewer.incoming.is_some() { "Waiting for paths..." } else { "No image loaded" }.to_string();
+    };
+    let mut line = format!("[{}/{}] {}", viewer.current_index + 1, viewer.images.len(), path.display());
+    match (&viewer.current_image, viewer.load_errors.get(path)) {
+        (Some(shown), _) => line += &format!("  {}×{}", shown.original_size.0, shown.original_size.1),
+        (None, Some(error)) => line += &format!("  {}", error),
+        (None, None) => line += "  loading...",
+    }
+    if viewer.marked.contains(path) {
+        line += "  *";
+    }
+    if !viewer.marked.is_empty() || viewer.marked_only {
+        line += &format!("  {} marked{}", viewer.marked.len(), if viewer.marked_only { " (marked only)" } else { "" });
+    }
+    if let Some((message, shown_at)) = &viewer.status
+        && shown_at.elapsed() < Duration::from_secs(4)
+    {
+        line += "  ";
+        line += message;
+    }
+    line
+}
+
+fn draw_status(out: &mut dyn Write, line: &str, (cols, rows): (u16, u16)) -> std::io::Result<()> {
+    let line: String = line.chars().take(cols as usize).collect();
+    out.queue(cursor::MoveTo(0, rows.saturating_sub(1)))?
+        .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
+        .queue(style::SetAttribute(style::Attribute::Reverse))?
+        .queue(style::Print(format!("{:<width$}", line, width = cols as usize)))?
+        .queue(style::SetAttribute(style::Attribute::Reset))?;
+    Ok(())
+}
+
+/// Features that only exist as window overlays or dialogs. They are closed
+/// again right after their key opens them.
+fn close_window_only_modes(viewer: &mut ImageViewer) {
+    let opened = viewer.rename_dialog.take().is_some()
+        | viewer.editor.take().is_some()
+        | viewer.duplicates.take().is_some()
+        | viewer.compare.take().is_some()
+        | viewer.inspector.take().is_some()
+        | viewer.histogram.take().is_some()
+        | viewer.stray_colors.take().is_some()
+        | std::mem::take(&mut viewer.show_adjustments)
+        | std::mem::take(&mut viewer.error_log.open)
+        | std::mem::take(&mut viewer.hud.open)
+        | viewer.recent.take().is_some();
+    if opened {
+        viewer.set_status("Not available in the terminal".to_string());
+    }
+}
+
+/// Run one key, and the text it typed, through the window's key bindings.
+/// Returns true if it asked to quit.
+fn press(viewer: &mut ImageViewer, ctx: &egui::Context, key: Option<(egui::Key, egui::Modifiers)>, text: Option<String>) -> bool {
+    if viewer.key_handler_prefix {
+        let Some((key, modifiers)) = key else { return false };
+        viewer.key_handler_prefix = false;
+        if key != egui::Key::Escape {
+            viewer.run_key_handler(keyhandler::key_name(key, modifiers), ctx);
+        }
+        return false;
+    }
+    let mut events: Vec<egui::Event> = key
+        .map(|(key, modifiers)| egui::Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers,
+        })
+        .into_iter()
+        .collect();
+    events.extend(text.map(egui::Event::Text));
+    let input = egui::RawInput {
+        modifiers: key.map(|(_, modifiers)| modifiers).unwrap_or_default(),
+        events,
+        ..Default::default()
+    };
+    let output = ctx.run(input, |ctx| viewer.handle_keys(ctx));
+    close_window_only_modes(viewer);
+    output
+        .viewport_output
+        .values()
+        .any(|viewport| viewport.commands.contains(&egui::ViewportCommand::Close))
+}
+
+fn event_loop(viewer: &mut ImageViewer, ctx: &egui::Context, out: &mut dyn Write, protocol: Protocol) -> std::io::Result<bool> {
+    // What is on screen, to redraw only when it changes
+    let mut shown_frame = None;
+    let mut shown_status = String::new();
+    loop {
+        viewer.poll_background();
+        viewer.announce_current_image();
+
+        let size = terminal::size()?;
+        let frame = (
+            viewer.images.get(viewer.current_index).cloned(),
+            viewer.current_image.is_some(),
+            viewer.current_rotation(),
+            viewer.current_adjustments(),
+            viewer.channel,
+            viewer.background,
+            viewer.filter,
+            size,
+        );
+        if shown_frame.as_ref() != Some(&frame) {
+            out.queue(terminal::Clear(terminal::ClearType::All))?;
+            if protocol == Protocol::Kitty {
+                write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+            }
+            if let Some(image) = current_frame(viewer) {
+                draw_image(out, viewer, &image, protocol, (size.0, size.1.saturating_sub(1)))?;
+            }
+            shown_frame = Some(frame);
+            shown_status.clear();
+        }
+        let status = status_line(viewer);
+        if status != shown_status {
+            draw_status(out, &status, size)?;
+            shown_status = status;
+        }
+        out.flush()?;
+
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(false);
+        }
+        if viewer.show_delete_confirm {
+            if let Some(path) = viewer.image_to_delete.clone()
+                && matches!(key.code, KeyCode::Char('y' | 'Y'))
+            {
+                match viewer.delete_image(&path) {
+                    Ok(()) => viewer.update_image_list_after_delete(),
+                    Err(e) => {
+                        viewer.report_error(format!("Failed to delete {}: {}", path.display(), e));
+                        viewer.set_status(format!("Failed to delete image: {}", e));
+                    }
+                }
+            }
+            viewer.show_delete_confirm = false;
+            viewer.image_to_delete = None;
+            continue;
+        }
+        let text = typed_text(&key);
+        let key = egui_key(&key);
+        if (key.is_some() || text.is_some()) && press(viewer, ctx, key, text) {
+            return Ok(true);
+        }
+    }
+}
+
+/// Browse `images` in the terminal with the window's key bindings. The
+/// viewer is the same, only without a window: decoding, preloading and
+/// the cache all work as usual.
+pub fn run(images: Vec<PathBuf>, incoming: Option<pathlist::PathStream>, mut options: ViewerOptions, protocol: Protocol) -> std::io::Result<()> {
+    // Marked paths are printed once the screen is restored
+    let separator = options.output_separator.take();
+    let ctx = egui::Context::default();
+    let mut viewer = ImageViewer::new(images, incoming, options, &ctx);
+    // Printed with the screen restored instead of over the image
+    viewer.error_log.echo = false;
+
+    let mut out = open_tty();
+    terminal::enable_raw_mode()?;
+    out.queue(terminal::EnterAlternateScreen)?.queue(cursor::Hide)?;
+    let result = event_loop(&mut viewer, &ctx, &mut out, protocol);
+
+    if protocol == Protocol::Kitty {
+        let _ = write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\");
+    }
+    let _ = out.queue(cursor::Show).and_then(|out| out.queue(terminal::LeaveAlternateScreen));
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+
+    viewer.save_session();
+    for message in viewer.error_log.messages() {
+        eprintln!("{}", message);
+    }
+    if let (Ok(true), Some(separator)) = (&result, separator) {
+        viewer.write_marked(&mut std::io::stdout().lock(), separator)?;
+    }
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_encoding() {
+        let key = |code, modifiers| egui_key(&KeyEvent::new(code, modifiers));
+        assert_eq!(key(KeyCode::Char('j'), KeyModifiers::NONE), Some((egui::Key::J, egui::Modifiers::NONE)));
+        assert_eq!(key(KeyCode::Char('M'), KeyModifiers::NONE), Some((egui::Key::M, egui::Modifiers::SHIFT)));
+        assert_eq!(key(KeyCode::Char('e'), KeyModifiers::CONTROL), Some((egui::Key::E, egui::Modifiers { ctrl: true, command: true, ..Default::default() })));
+        assert_eq!(key(KeyCode::F(2), KeyModifiers::NONE), Some((egui::Key::F2, egui::Modifiers::NONE)));
+        assert_eq!(key(KeyCode::Char('/'), KeyModifiers::NONE), None);
+        assert_eq!(typed_text(&KeyEvent::new(KeyCode::Char('/'), KeyModifiers::NONE)), Some("/".to_string()));
+        assert_eq!(typed_text(&KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL)), None);
+        assert_eq!(fit((400, 200), (100, 100)), (100, 50));
+        assert_eq!("sixel".parse::<Protocol>(), Ok(Protocol::Sixel));
+
+        // 8 rows of one color: two bands, the first a run of full sixels
+        let sixel = String::from_utf8(encode_sixel(&RgbImage::from_pixel(5, 8, image::Rgb([255, 0, 0])))).unwrap();
+        assert!(sixel.starts_with("\x1bPq\"1;1;5;8"));
+        assert!(sixel.contains("!5~-"));
+        assert!(sixel.ends_with("-\x1b\\"));
+    }
+}
@@ -0,0 +1,289 @@
+use eframe::egui;
+use image::{DynamicImage, GenericImageView};
+use serde::Deserialize;
+use crate::loader::{self, LoadHandle, Loader};
+use std::path::{Path, PathBuf};
+
+/// Color flagging transparent pixels that still carry RGB values.
+const STRAY_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 0, 255);
+
+/// An `#rrggbb` color from the config file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgb(pub [u8; 3]);
+
+impl std::str::FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Rgb([r, g, b])),
+            _ => Err(format!("invalid color '{}', expected #rrggbb", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rgb {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<Rgb> for egui::Color32 {
+    fn from(Rgb([r, g, b]): Rgb) -> Self {
+        egui::Color32::from_rgb(r, g, b)
+    }
+}
+
+/// `[background]` section of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    /// Checkerboard square size in points
+    pub checker_size: f32,
+    pub checker_colors: [Rgb; 2],
+    /// Extra color added to the cycle
+    pub custom: Option<Rgb>,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            checker_size: 12.0,
+            checker_colors: [Rgb([0x99, 0x99, 0x99]), Rgb([0x66, 0x66, 0x66])],
+            custom: None,
+        }
+    }
+}
+
+/// What's drawn behind transparent pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Background {
+    #[default]
+    Checkerboard,
+    Black,
+    White,
+    Custom,
+    /// The checkerboard under the image drawn as if its colors were
+    /// already multiplied by alpha, the way premultiplied pipelines show
+    /// it: color left in transparent pixels shows up instead of vanishing
+    Premultiplied,
+}
+
+impl Background {
+    pub const ALL: [Background; 5] = [
+        Background::Checkerboard,
+        Background::Black,
+        Background::White,
+        Background::Custom,
+        Background::Premultiplied,
+    ];
+
+    /// The next background, skipping the custom color if none is configured.
+    pub fn next(self, config: &BackgroundConfig) -> Self {
+        match self {
+            Background::Checkerboard => Background::Black,
+            Background::Black => Background::White,
+            Background::White if config.custom.is_some() => Background::Custom,
+            Background::White | Background::Custom => Background::Premultiplied,
+            Background::Premultiplied => Background::Checkerboard,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Background::Checkerboard => "checkerboard",
+            Background::Black => "black",
+            Background::White => "white",
+            Background::Custom => "custom color",
+            Background::Premultiplied => "premultiplied preview",
+        }
+    }
+
+    pub fn paint(self, painter: &egui::Painter, rect: egui::Rect, config: &BackgroundConfig) {
+        let color = match self {
+            Background::Black => egui::Color32::BLACK,
+            Background::White => egui::Color32::WHITE,
+            Background::Custom => config.custom.map_or(egui::Color32::BLACK, Into::into),
+            Background::Checkerboard | Background::Premultiplied => {
+                paint_checkerboard(painter, rect, config);
+                return;
+            }
+        };
+        painter.rect_filled(rect, 0.0, color);
+    }
+
+    /// `image` composited onto this background, for outputs without alpha.
+    pub fn flatten(self, image: &image::RgbaImage, config: &BackgroundConfig) -> image::RgbImage {
+        let solid = match self {
+            Background::Black => Some([0, 0, 0]),
+            Background::White => Some([255, 255, 255]),
+            Background::Custom => Some(config.custom.map_or([0, 0, 0], |c| c.0)),
+            Background::Checkerboard | Background::Premultiplied => None,
+        };
+        let size = config.checker_size.max(2.0) as u32;
+        let [light, dark] = config.checker_colors.map(|c| c.0);
+        image::RgbImage::from_fn(image.width(), image.height(), |x, y| {
+            let under = solid.unwrap_or(if (x / size + y / size).is_multiple_of(2) { light } else { dark });
+            let [r, g, b, a] = image.get_pixel(x, y).0;
+            let blend = |top: u8, under: u8| {
+                // Premultiplied color is already scaled by alpha, and may exceed it
+                let top = if self == Background::Premultiplied { top as u32 * 255 } else { top as u32 * a as u32 };
+                ((top + under as u32 * (255 - a as u32)) / 255).min(255) as u8
+            };
+            image::Rgb([blend(r, under[0]), blend(g, under[1]), blend(b, under[2])])
+        })
+    }
+}
+
+fn paint_checkerboard(painter: &egui::Painter, rect: egui::Rect, config: &BackgroundConfig) {
+    // Only the squares inside the visible part, which is small even when zoomed in
+    let visible = rect.intersect(painter.clip_rect());
+    if !visible.is_positive() {
+        return;
+    }
+    let size = config.checker_size.max(2.0);
+    let [light, dark] = config.checker_colors.map(egui::Color32::from);
+    painter.rect_filled(visible, 0.0, light);
+
+    let first_col = ((visible.left() - rect.left()) / size).floor() as i64;
+    let first_row = ((visible.top() - rect.top()) / size).floor() as i64;
+    let cols = (visible.width() / size).ceil() as i64 + 1;
+    let rows = (visible.height() / size).ceil() as i64 + 1;
+    for row in first_row..first_row + rows {
+        for col in first_col..first_col + cols {
+            if (row + col) % 2 == 0 {
+                continue;
+            }
+            let min = rect.min + egui::vec2(col as f32, row as f32) * size;
+            let square = egui::Rect::from_min_size(min, egui::vec2(size, size)).intersect(visible);
+            if square.is_positive() {
+                painter.rect_filled(square, 0.0, dark);
+            }
+        }
+    }
+}
+
+/// Mask of fully transparent pixels with non-zero RGB, scaled down to
+/// `size` so that any flagged pixel in a block flags the whole block.
+/// Returns the mask and the number of flagged pixels in the original.
+pub fn stray_color_mask(image: &DynamicImage, size: (u32, u32)) -> (image::RgbaImage, u64) {
+    let (w, h) = image.dimensions();
+    let (mw, mh) = (size.0.max(1), size.1.max(1));
+    let mut mask = image::RgbaImage::new(mw, mh);
+    let mut count = 0;
+    let [r, g, b] = [STRAY_COLOR.r(), STRAY_COLOR.g(), STRAY_COLOR.b()];
+    for (x, y, pixel) in image.to_rgba8().enumerate_pixels() {
+        let [pr, pg, pb, pa] = pixel.0;
+        if pa == 0 && (pr, pg, pb) != (0, 0, 0) {
+            count += 1;
+            let mx = (x as u64 * mw as u64 / w as u64) as u32;
+            let my = (y as u64 * mh as u64 / h as u64) as u32;
+            mask.put_pixel(mx, my, image::Rgba([r, g, b, 255]));
+        }
+    }
+    (mask, count)
+}
+
+/// A mask from `stray_color_mask`, `None` if the file couldn't be decoded.
+type Mask<T> = Option<(T, u64)>;
+
+/// Overlay marking transparent pixels that still have color, computed
+/// from the full-resolution file by the loader.
+#[derive(Default)]
+pub struct StrayColorOverlay {
+    current: Option<(PathBuf, u32, Mask<egui::TextureHandle>)>,
+    pending: Option<LoadHandle<(image::RgbaImage, u64)>>,
+}
+
+impl StrayColorOverlay {
+    /// Draw the mask over `rect`, where the image is shown rotated by
+    /// `rotation` from a display copy of `display_size`.
+    pub fn show(&mut self, ui: &egui::Ui, rect: egui::Rect, path: &Path, display_size: (u32, u32), rotation: u32, loader: &Loader) {
+        let ctx = ui.ctx();
+        if let Some(handle) = &mut self.pending
+            && handle.path() == path
+            && let Some(result) = handle.try_take()
+        {
+            let texture = result.ok().map(|(mask, count)| {
+                let mask = crate::rotate_image(&DynamicImage::ImageRgba8(mask), rotation).to_rgba8();
+                let image = egui::ColorImage::from_rgba_unmultiplied([mask.width() as usize, mask.height() as usize], &mask);
+                (ctx.load_texture("stray_color_mask", image, egui::TextureOptions::NEAREST), count)
+            });
+            self.current = Some((path.to_path_buf(), rotation, texture));
+            self.pending = None;
+        }
+
+        let is_current = matches!(&self.current, Some((current, r, _)) if current == path && *r == rotation);
+        let is_pending = matches!(&self.pending, Some(handle) if handle.path() == path);
+        if !is_current && !is_pending {
+            // Only the image on screen is worth checking
+            if let Some(stale) = self.pending.take() {
+                stale.cancel();
+            }
+            let handle = loader.load_full(path.to_path_buf(), loader::FULL_PRIORITY, move |image| stray_color_mask(&image, display_size));
+            self.pending = Some(handle);
+        }
+
+        let label = match &self.current {
+            Some((_, _, Some((texture, count)))) if is_current => {
+                if *count > 0 {
+                    ui.painter().image(
+                        texture.id(),
+                        rect,
+                        egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                        egui::Color32::WHITE,
+                    );
+                }
+                format!("{} transparent pixels with color", count)
+            }
+            Some((_, _, None)) if is_current => "Could not check transparency".to_string(),
+            _ => "Checking transparency...".to_string(),
+        };
+        let clip = ui.clip_rect();
+        ui.painter().text(
+            egui::pos2(clip.center().x, clip.top() + 10.0),
+            egui::Align2::CENTER_TOP,
+            label,
+            egui::FontId::proportional(14.0),
+            STRAY_COLOR,
+        );
+    }
+}
+
+impl Drop for StrayColorOverlay {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.pending {
+            pending.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stray_color_mask_and_colors() {
+        let mut rgba = image::RgbaImage::new(4, 4);
+        rgba.put_pixel(3, 3, image::Rgba([10, 0, 0, 0]));
+        rgba.put_pixel(0, 0, image::Rgba([10, 0, 0, 1]));
+        let (mask, count) = stray_color_mask(&DynamicImage::ImageRgba8(rgba), (2, 2));
+        assert_eq!(count, 1);
+        assert_eq!(mask.get_pixel(1, 1).0[3], 255);
+        assert_eq!(mask.get_pixel(0, 0).0[3], 0);
+
+        assert_eq!("#ff8000".parse::<Rgb>(), Ok(Rgb([255, 128, 0])));
+        assert!("#ff80".parse::<Rgb>().is_err());
+
+        let config = BackgroundConfig::default();
+        assert_eq!(Background::White.next(&config), Background::Premultiplied);
+        assert_eq!(Background::Premultiplied.next(&config), Background::Checkerboard);
+        let flat = Background::White.flatten(&image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 128])), &config);
+        assert_eq!(flat.get_pixel(0, 0).0, [127, 127, 127]);
+        // A transparent pixel keeps its color when taken as premultiplied
+        let stray = image::RgbaImage::from_pixel(1, 1, image::Rgba([200, 0, 0, 0]));
+        assert_eq!(Background::Premultiplied.flatten(&stray, &config).get_pixel(0, 0).0, [255, 0x99, 0x99]);
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+/// `$XDG_DATA_HOME/Trash`, falling back to `~/.local/share/Trash`.
+fn trash_dir() -> Option<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(data.join("Trash"))
+}
+
+/// Move `path` to the user's trash following the freedesktop.org spec, so
+/// file managers can restore it.
+pub fn move_to_trash(path: &Path) -> std::io::Result<()> {
+    let trash = trash_dir().ok_or_else(|| std::io::Error::other("no home directory"))?;
+    move_to_trash_in(path, &trash)
+}
+
+fn move_to_trash_in(path: &Path, trash: &Path) -> std::io::Result<()> {
+    let files = trash.join("files");
+    let info = trash.join("info");
+    std::fs::create_dir_all(&files)?;
+    std::fs::create_dir_all(&info)?;
+
+    let original = std::path::absolute(path)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("not a file"))?
+        .to_string_lossy()
+        .into_owned();
+
+    // Claim a free name by creating its .trashinfo first
+    let (trashed_name, mut info_file) = (0..)
+        .map(|n| if n == 0 { name.clone() } else { format!("{}.{}", name, n) })
+        .find_map(|candidate| {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(info.join(format!("{}.trashinfo", candidate)))
+                .ok()?;
+            Some((candidate, file))
+        })
+        .unwrap();
+
+    use std::io::Write;
+    writeln!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}",
+        percent_encode(&original.to_string_lossy()),
+        deletion_date()
+    )?;
+
+    let dest = files.join(&trashed_name);
+    if std::fs::rename(path, &dest).is_err() {
+        // Trash is on another filesystem
+        if let Err(e) = std::fs::copy(path, &dest).and_then(|_| std::fs::remove_file(path)) {
+            let _ = std::fs::remove_file(info.join(format!("{}.trashinfo", trashed_name)));
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut out = String::new();
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// Current UTC time as `YYYY-MM-DDThh:mm:ss`.
+fn deletion_date() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_to_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("Trash");
+
+        for _ in 0..2 {
+            let file = dir.path().join("a b.png");
+            std::fs::write(&file, b"x").unwrap();
+            move_to_trash_in(&file, &trash).unwrap();
+            assert!(!file.exists());
+        }
+
+        assert!(trash.join("files").join("a b.png").exists());
+        assert!(trash.join("files").join("a b.png.1").exists());
+        let info = std::fs::read_to_string(trash.join("info").join("a b.png.trashinfo")).unwrap();
+        assert!(info.contains("a%20b.png"));
+    }
+}
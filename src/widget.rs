@@ -0,0 +1,134 @@
+use crate::cache::CachedImage;
+use crate::scaling::{self, FilterMode};
+use crate::transparency::{Background, BackgroundConfig};
+use eframe::egui;
+use image::DynamicImage;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZoomMode {
+    /// Scale the image to fit the available space
+    Fit,
+    /// Fixed scale factor relative to the display image
+    Scale(f32),
+}
+
+type TextureOverride<'a> = Box<dyn FnOnce(&DynamicImage, egui::TextureOptions) -> Option<egui::TextureId> + 'a>;
+
+/// A cached image placed in any `egui::Ui`: fitted and centered, or zoomed
+/// inside a scroll area. The texture is created on first use and kept in
+/// the cache entry.
+pub struct ImageView<'a> {
+    image: &'a mut CachedImage,
+    zoom: ZoomMode,
+    filter: FilterMode,
+    integer_scaling: bool,
+    background: Option<(Background, &'a BackgroundConfig)>,
+    texture_override: Option<TextureOverride<'a>>,
+}
+
+/// Where the image was drawn.
+pub struct ImageViewResponse {
+    /// The whole image on screen, which may reach past the visible area
+    /// when zoomed in
+    pub rect: egui::Rect,
+    /// Screen points per display-image pixel
+    pub scale: f32,
+}
+
+impl<'a> ImageView<'a> {
+    pub fn new(image: &'a mut CachedImage) -> Self {
+        Self {
+            image,
+            zoom: ZoomMode::Fit,
+            filter: FilterMode::default(),
+            integer_scaling: false,
+            background: None,
+            texture_override: None,
+        }
+    }
+
+    pub fn zoom(mut self, zoom: ZoomMode) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Round the scale to whole multiples (or fractions) of the display size.
+    pub fn integer_scaling(mut self, integer_scaling: bool) -> Self {
+        self.integer_scaling = integer_scaling;
+        self
+    }
+
+    /// Paint `background` behind the image so it shows through transparent pixels.
+    pub fn background(mut self, background: Background, config: &'a BackgroundConfig) -> Self {
+        self.background = Some((background, config));
+        self
+    }
+
+    /// Draw another texture of the same size instead, such as an edited
+    /// copy. `texture` gets the unrotated display image and the sampling it
+    /// is drawn with, and may return `None` to keep the image's own texture.
+    pub fn texture_override(
+        mut self,
+        texture: impl FnOnce(&DynamicImage, egui::TextureOptions) -> Option<egui::TextureId> + 'a,
+    ) -> Self {
+        self.texture_override = Some(Box::new(texture));
+        self
+    }
+
+    pub fn show(self, ui: &mut egui::Ui) -> ImageViewResponse {
+        let (width, height) = self.image.rotated_size();
+        let available = ui.available_size();
+        let scale = match self.zoom {
+            ZoomMode::Fit => (available.x / width as f32).min(available.y / height as f32),
+            ZoomMode::Scale(factor) => factor,
+        };
+        let scale = if self.integer_scaling { scaling::integer_scale(scale) } else { scale };
+        let size = egui::vec2(width as f32, height as f32) * scale;
+        let options = self.filter.texture_options(scale, (width, height));
+
+        // Recreated when the sampling changes, rotation clears it
+        let image = self.image;
+        let texture = match &image.texture {
+            Some((texture, shown)) if *shown == options => texture.clone(),
+            _ => {
+                let _span = tracing::debug_span!("texture_upload", size = ?(width, height)).entered();
+                let rotated = crate::rotate_image(&image.display_image, image.rotation);
+                let texture = ui.ctx().load_texture(
+                    "image_view",
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        [rotated.width() as usize, rotated.height() as usize],
+                        &rotated.to_rgba8(),
+                    ),
+                    options,
+                );
+                image.texture = Some((texture.clone(), options));
+                texture
+            }
+        };
+        let texture_id = self
+            .texture_override
+            .and_then(|texture| texture(&image.display_image, options))
+            .unwrap_or(texture.id());
+
+        // Background first so it shows through transparent pixels
+        let background = self.background;
+        let draw = |ui: &mut egui::Ui| {
+            let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+            if let Some((background, config)) = background {
+                background.paint(ui.painter(), rect, config);
+            }
+            egui::Image::new((texture_id, size)).paint_at(ui, rect);
+            rect
+        };
+        let rect = match self.zoom {
+            ZoomMode::Fit => ui.centered_and_justified(draw).inner,
+            ZoomMode::Scale(_) => egui::ScrollArea::both().show(ui, draw).inner,
+        };
+        ImageViewResponse { rect, scale }
+    }
+}